@@ -1,4 +1,4 @@
-#[allow(dead_code)]
+#[allow(dead_code, unused_imports)]
 #[path="../src/lib.rs"]
 mod paq;
 mod utils;
@@ -0,0 +1,342 @@
+use std::{
+    collections::{
+        BTreeSet,
+        HashMap,
+    },
+    fs::File,
+    io::{
+        self,
+        prelude::*,
+        BufReader,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+use flate2::read::MultiGzDecoder;
+use tar::EntryType;
+use xz2::read::XzDecoder;
+use zip::ZipArchive;
+
+use super::{
+    error::Error,
+    manifest::{
+        Entry,
+        EntryKind,
+    },
+    metadata::MetadataEncoder,
+    options::HashOptions,
+    walk::PathPatterns,
+    EntryHasher,
+    FILE_BUFFER_SIZE,
+};
+
+
+/// Archive format detected from leading bytes of source file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ArchiveFormat {
+    Tar,
+    TarGzip,
+    TarZstd,
+    TarXz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Format of leading bytes, with plain tar identified by POSIX or GNU magic of first header.
+    fn detect(magic: &[u8]) -> Option<Self> {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Some(ArchiveFormat::TarGzip)
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(ArchiveFormat::TarZstd)
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(ArchiveFormat::TarXz)
+        } else if magic.starts_with(b"PK\x03\x04") || magic.starts_with(b"PK\x05\x06") {
+            Some(ArchiveFormat::Zip)
+        } else if magic.get(257..262) == Some(b"ustar") {
+            Some(ArchiveFormat::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+/// Leading bytes read to detect format, covering magic of first tar header.
+const MAGIC_LEN: u64 = 262;
+
+/// Hash entries of tar (plain, gzip, zstd or xz compressed) or zip archive as if extracted to directory.
+///
+/// Paths are normalized as extraction would (`./` and `/` suffixes removed) and missing parent directories
+/// are added, so root matches hash of extracted directory. Duplicate paths, absolute paths, `..` components
+/// and hard links fail with [`Error::Archive`], as extracted directory would depend on extraction tool.
+pub(crate) fn archive_entries(source: &Path, options: &HashOptions) -> Result<Vec<Entry>, Error> {
+    check_options(options)?;
+    let mut file = File::open(source).map_err(|source| Error::Open {
+        path: PathBuf::new(),
+        source,
+    })?;
+    let mut entries = ArchiveEntries::new(options)?;
+    let (format, magic) = detect_format(&mut file)?;
+    if format == ArchiveFormat::Zip {
+        // zip central directory is read from end of file
+        file.rewind().map_err(read_error)?;
+        read_zip(BufReader::with_capacity(FILE_BUFFER_SIZE, file), &mut entries)?;
+    } else {
+        read_tar_format(io::Cursor::new(magic).chain(file), format, &mut entries)?;
+    }
+    entries.finish()
+}
+
+/// Hash entries of tar stream (plain, gzip, zstd or xz compressed) as if extracted to directory.
+///
+/// See [`archive_entries`]; zip archives cannot be read from stream.
+pub(crate) fn tar_entries(mut reader: impl Read, options: &HashOptions) -> Result<Vec<Entry>, Error> {
+    check_options(options)?;
+    let mut entries = ArchiveEntries::new(options)?;
+    let (format, magic) = detect_format(&mut reader)?;
+    read_tar_format(io::Cursor::new(magic).chain(reader), format, &mut entries)?;
+    entries.finish()
+}
+
+/// Detect format, returning leading bytes consumed from reader.
+fn detect_format(reader: &mut impl Read) -> Result<(ArchiveFormat, Vec<u8>), Error> {
+    let mut magic = Vec::with_capacity(MAGIC_LEN as usize);
+    reader.take(MAGIC_LEN).read_to_end(&mut magic).map_err(read_error)?;
+    let format = ArchiveFormat::detect(&magic).ok_or_else(|| Error::Archive {
+        path: PathBuf::new(),
+        reason: "unrecognized archive format".to_owned(),
+    })?;
+    Ok((format, magic))
+}
+
+fn read_tar_format(reader: impl Read, format: ArchiveFormat, entries: &mut ArchiveEntries) -> Result<(), Error> {
+    let reader = BufReader::with_capacity(FILE_BUFFER_SIZE, reader);
+    match format {
+        ArchiveFormat::Tar => read_tar(reader, entries),
+        ArchiveFormat::TarGzip => read_tar(MultiGzDecoder::new(reader), entries),
+        ArchiveFormat::TarZstd => read_tar(zstd::Decoder::with_buffer(reader).map_err(read_error)?, entries),
+        ArchiveFormat::TarXz => read_tar(XzDecoder::new_multi_decoder(reader), entries),
+        ArchiveFormat::Zip => Err(Error::Archive {
+            path: PathBuf::new(),
+            reason: "zip archives cannot be read from stream".to_owned(),
+        }),
+    }
+}
+
+/// Fail for options that depend on file system rather than archive contents.
+fn check_options(options: &HashOptions) -> Result<(), Error> {
+    let unsupported = if options.ignore_files {
+        "ignore files are not supported"
+    } else if options.follow_symlinks {
+        "following symlinks is not supported"
+    } else if !MetadataEncoder::new(options).is_empty() {
+        "hashing metadata is not supported"
+    } else {
+        return Ok(());
+    };
+    Err(Error::Archive {
+        path: PathBuf::new(),
+        reason: unsupported.to_owned(),
+    })
+}
+
+fn read_tar(reader: impl Read, entries: &mut ArchiveEntries) -> Result<(), Error> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries().map_err(read_error)? {
+        let mut entry = entry.map_err(read_error)?;
+        let name = entry.path_bytes().into_owned();
+        let kind = match entry.header().entry_type() {
+            EntryType::Regular | EntryType::Continuous => EntryKind::File,
+            EntryType::Directory => EntryKind::Dir,
+            EntryType::Symlink => EntryKind::Symlink,
+            // global extended headers apply to following entries, which tar reader handles
+            EntryType::XGlobalHeader => continue,
+            EntryType::Link => return Err(archive_error(&name, "hard links are not supported")),
+            entry_type => {
+                return Err(archive_error(&name, &format!("unsupported entry type {entry_type:?}")));
+            }
+        };
+        if kind == EntryKind::Symlink {
+            let target = entry
+                .link_name_bytes()
+                .ok_or_else(|| archive_error(&name, "symlink has no target"))?
+                .into_owned();
+            entries.add(&name, kind, target.as_slice())?;
+        } else {
+            entries.add(&name, kind, &mut entry)?;
+        }
+    }
+    Ok(())
+}
+
+fn read_zip(reader: impl Read + Seek, entries: &mut ArchiveEntries) -> Result<(), Error> {
+    let mut archive = ZipArchive::new(reader).map_err(|e| read_error(e.into()))?;
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index).map_err(|e| read_error(e.into()))?;
+        let name = file.name_raw().to_vec();
+        // symlink content is target path
+        let kind = if file.is_dir() {
+            EntryKind::Dir
+        } else if file.is_symlink() {
+            EntryKind::Symlink
+        } else {
+            EntryKind::File
+        };
+        entries.add(&name, kind, &mut file)?;
+    }
+    Ok(())
+}
+
+/// Entries hashed while reading archive, with kinds of every path for duplicate and parent checks.
+struct ArchiveEntries<'a> {
+    options: &'a HashOptions,
+    patterns: PathPatterns,
+    kinds: HashMap<PathBuf, EntryKind>,
+    entries: Vec<Entry>,
+}
+
+impl<'a> ArchiveEntries<'a> {
+    fn new(options: &'a HashOptions) -> Result<Self, Error> {
+        Ok(ArchiveEntries {
+            options,
+            patterns: PathPatterns::new(&options.include, &options.exclude)?,
+            kinds: HashMap::new(),
+            entries: Vec::new(),
+        })
+    }
+
+    fn add(&mut self, name: &[u8], kind: EntryKind, mut content: impl Read) -> Result<(), Error> {
+        let normalized = normalize_path(name).map_err(|reason| archive_error(name, reason))?;
+        let path = to_path(normalized, name)?;
+        // source root is always a directory
+        if path.as_os_str().is_empty() {
+            return match kind {
+                EntryKind::Dir => Ok(()),
+                _ => Err(archive_error(name, "source root must be a directory")),
+            };
+        }
+        if self.kinds.insert(path.clone(), kind).is_some() {
+            return Err(archive_error(name, "duplicate entry"));
+        }
+        if self.is_filtered(&path, kind == EntryKind::Dir) {
+            return Ok(());
+        }
+
+        let mut hasher = EntryHasher::new(self.options, true);
+        hasher.update_path(path.as_os_str().as_encoded_bytes(), kind, self.options.hash_version);
+        let mut buffer = vec![0; FILE_BUFFER_SIZE];
+        loop {
+            let buffer_size = content.read(&mut buffer).map_err(|source| Error::Read {
+                path: path.clone(),
+                source,
+            })?;
+            if buffer_size == 0 { break; }
+            hasher.update_content(&buffer[..buffer_size]);
+        }
+        let entry = hasher.finish(path, kind, self.options);
+        self.options.entry_hashed(kind, entry.size);
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Filtered as walker would, which prunes hidden and excluded directories.
+    fn is_filtered(&self, path: &Path, is_dir: bool) -> bool {
+        let is_hidden = |path: &Path| {
+            path.file_name()
+                .map(|name| name.as_encoded_bytes())
+                .and_then(|name| name.split_first())
+                .is_some_and(|(first, rest)| *first == b'.' && !rest.is_empty())
+        };
+        path.ancestors()
+            .filter(|ancestor| !ancestor.as_os_str().is_empty())
+            .enumerate()
+            .any(|(depth, ancestor)| {
+                let ancestor_is_dir = depth > 0 || is_dir;
+                (self.options.ignore_hidden && is_hidden(ancestor))
+                    || self.patterns.is_excluded(ancestor, ancestor_is_dir)
+            })
+    }
+
+    /// Add missing parent directories, failing if parent is not a directory.
+    fn finish(mut self) -> Result<Vec<Entry>, Error> {
+        let mut parents = BTreeSet::new();
+        parents.insert(PathBuf::new());
+        for path in self.kinds.keys() {
+            for parent in path.ancestors().skip(1) {
+                match self.kinds.get(parent) {
+                    Some(EntryKind::Dir) => {}
+                    Some(_) => {
+                        return Err(archive_error(
+                            path.as_os_str().as_encoded_bytes(),
+                            "parent is not a directory",
+                        ));
+                    }
+                    None => {
+                        parents.insert(parent.to_path_buf());
+                    }
+                }
+            }
+        }
+        for parent in parents {
+            if !parent.as_os_str().is_empty() && self.is_filtered(&parent, true) {
+                continue;
+            }
+            let mut hasher = EntryHasher::new(self.options, true);
+            hasher.update_path(parent.as_os_str().as_encoded_bytes(), EntryKind::Dir, self.options.hash_version);
+            self.entries.push(hasher.finish(parent, EntryKind::Dir, self.options));
+        }
+        Ok(self.entries)
+    }
+}
+
+/// Relative path with `.` and empty components removed, failing for absolute paths and `..` components.
+fn normalize_path(name: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let is_drive = name.len() >= 2 && name[0].is_ascii_alphabetic() && name[1] == b':';
+    if name.starts_with(b"/") || name.starts_with(b"\\") || is_drive {
+        return Err("absolute path");
+    }
+    let mut components = Vec::new();
+    for component in name.split(|byte| *byte == b'/') {
+        match component {
+            b"" | b"." => continue,
+            b".." => return Err("path contains `..` component"),
+            component => components.push(component),
+        }
+    }
+    Ok(components.join(&b'/'))
+}
+
+#[cfg(target_family = "unix")]
+fn to_path(bytes: Vec<u8>, _: &[u8]) -> Result<PathBuf, Error> {
+    use std::{
+        ffi::OsString,
+        os::unix::ffi::OsStringExt,
+    };
+
+    Ok(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+#[cfg(not(target_family = "unix"))]
+fn to_path(bytes: Vec<u8>, name: &[u8]) -> Result<PathBuf, Error> {
+    String::from_utf8(bytes)
+        .map(PathBuf::from)
+        .map_err(|_| Error::PathEncoding {
+            path: PathBuf::from(String::from_utf8_lossy(name).into_owned()),
+        })
+}
+
+fn archive_error(name: &[u8], reason: &str) -> Error {
+    Error::Archive {
+        path: PathBuf::from(String::from_utf8_lossy(name).into_owned()),
+        reason: reason.to_owned(),
+    }
+}
+
+fn read_error(source: io::Error) -> Error {
+    Error::Read {
+        path: PathBuf::new(),
+        source,
+    }
+}
@@ -7,25 +7,85 @@
 
 use clap::{
     builder::TypedValueParser, crate_description, crate_name, crate_version, error::ContextKind,
-    error::ContextValue, error::ErrorKind, Arg, ArgAction, Command,
+    error::ContextValue, error::ErrorKind, parser::ValueSource, Arg, ArgAction, ArgMatches, Command,
+};
+use paq::{
+    Diff,
+    EntryError,
+    EntryKind,
+    HashOptions,
+    Manifest,
+    Parallelism,
+    Progress,
+    Report,
+    ScanTotals,
+    Tree,
+};
+use rayon::{
+    prelude::*,
+    ThreadPoolBuilder,
 };
-use paq::hash_source;
 use std::{
-    fs::File,
+    fs::{
+        self,
+        File,
+    },
     io::{
+        self,
+        BufWriter,
         Error,
+        IsTerminal,
         Write,
     },
+    ops::ControlFlow,
     path::{
         Path,
         PathBuf,
-    }
+    },
+    process,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        mpsc,
+        Arc,
+        OnceLock,
+    },
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
 };
 
+const OUTPUT_DEFAULT: &str = "<src>.paq";
+const MANIFEST_STDOUT: &str = "-";
+const SOURCE_STDIN: &str = "-";
+
+const EXIT_MISMATCH: i32 = 1;
+const EXIT_FAILURE: i32 = 3;
+const EXIT_WALK: i32 = 4;
+const EXIT_OPEN: i32 = 5;
+const EXIT_READ: i32 = 6;
+const EXIT_METADATA: i32 = 7;
+const EXIT_READ_LINK: i32 = 8;
+const EXIT_PATH_ENCODING: i32 = 9;
+const EXIT_IGNORE: i32 = 10;
+const EXIT_PATTERN: i32 = 11;
+const EXIT_LOOP: i32 = 12;
+const EXIT_DANGLING_SYMLINK: i32 = 13;
+const EXIT_XATTR: i32 = 14;
+const EXIT_WATCH: i32 = 15;
+const EXIT_ARCHIVE: i32 = 16;
+const EXIT_PARTIAL: i32 = 17;
+const EXIT_MANIFEST: i32 = 18;
+
 #[derive(Copy, Clone, Debug)]
 #[non_exhaustive]
 pub struct PathBufferValueParser {
     validate_exists: bool,
+    allow_stdin: bool,
 }
 
 impl TypedValueParser for PathBufferValueParser {
@@ -38,6 +98,9 @@ impl TypedValueParser for PathBufferValueParser {
         value: &std::ffi::OsStr,
     ) -> Result<Self::Value, clap::Error> {
         let path = PathBuf::from(value);
+        if self.allow_stdin && path == Path::new(SOURCE_STDIN) {
+            return Ok(path);
+        }
         if self.validate_exists && !path.exists() {
             let mut err = clap::Error::new(ErrorKind::InvalidValue).with_cmd(cmd);
             err.insert(
@@ -58,39 +121,909 @@ impl TypedValueParser for PathBufferValueParser {
     }
 }
 
-fn derive_output_filepath(source: &Path) -> PathBuf {
-    let source_canonical = source.canonicalize().unwrap();
-    let source_filename = source_canonical.file_name().unwrap().to_str().unwrap();
-    let mut path_buffer = PathBuf::from(source_canonical.parent().unwrap());
-    path_buffer.push(format!("{source_filename}.paq"));
-    path_buffer
+fn derive_output_filepath(source: &Path) -> Result<PathBuf, Error> {
+    let source_canonical = source.canonicalize()?;
+    let mut source_filename = source_canonical
+        .file_name()
+        .unwrap_or(source_canonical.as_os_str())
+        .to_os_string();
+    source_filename.push(".paq");
+    let mut path_buffer = source_canonical
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    path_buffer.push(source_filename);
+    Ok(path_buffer)
+}
+
+fn write_hashfile(filepath: &PathBuf, report: &Report) -> Result<(), Error> {
+    let mut writer = BufWriter::new(File::create(filepath)?);
+    report.write_json(&mut writer)?;
+    writeln!(writer)?;
+    writer.flush()
+}
+
+fn write_manifest(filepath: &Path, manifest: &Manifest) -> Result<(), Error> {
+    if filepath == Path::new(MANIFEST_STDOUT) {
+        let mut stdout = io::stdout().lock();
+        manifest.write_json(&mut stdout)?;
+        writeln!(stdout)
+    } else {
+        let mut writer = BufWriter::new(File::create(filepath)?);
+        manifest.write_json(&mut writer)?;
+        writer.flush()
+    }
+}
+
+fn exit_code(err: &paq::Error) -> i32 {
+    match err {
+        paq::Error::Walk { .. } => EXIT_WALK,
+        paq::Error::Open { .. } => EXIT_OPEN,
+        paq::Error::Read { .. } => EXIT_READ,
+        paq::Error::Metadata { .. } => EXIT_METADATA,
+        paq::Error::ReadLink { .. } => EXIT_READ_LINK,
+        paq::Error::PathEncoding { .. } => EXIT_PATH_ENCODING,
+        paq::Error::Ignore { .. } => EXIT_IGNORE,
+        paq::Error::Pattern { .. } => EXIT_PATTERN,
+        paq::Error::Loop { .. } => EXIT_LOOP,
+        paq::Error::DanglingSymlink { .. } => EXIT_DANGLING_SYMLINK,
+        paq::Error::Xattr { .. } => EXIT_XATTR,
+        paq::Error::Watch { .. } => EXIT_WATCH,
+        paq::Error::Archive { .. } => EXIT_ARCHIVE,
+        _ => EXIT_FAILURE,
+    }
+}
+
+fn exit_with_error(message: impl std::fmt::Display, code: i32) -> ! {
+    eprintln!("error: {message}");
+    process::exit(code)
+}
+
+fn read_hashfile(filepath: &Path, key: Option<[u8; 32]>) -> Result<(String, Option<HashOptions>), Error> {
+    let contents = fs::read_to_string(filepath)?;
+    // hash files contain JSON report (or JSON string before reports were versioned),
+    // manifests contain JSON object with root, options and entries
+    match serde_json::from_str::<serde_json::Value>(&contents)? {
+        serde_json::Value::String(hash) => Ok((hash, None)),
+        serde_json::Value::Object(object) if object.contains_key("version") => {
+            let report = match key {
+                Some(key) => Report::read_json_keyed(contents.as_bytes(), key)?,
+                None => Report::read_json(contents.as_bytes())?,
+            };
+            Ok((report.root().to_owned(), Some(report.options().clone())))
+        }
+        serde_json::Value::Object(_) => {
+            let manifest = read_manifest(contents.as_bytes(), key)?;
+            Ok((manifest.root().to_owned(), Some(manifest.options().clone())))
+        }
+        _ => Err(Error::new(io::ErrorKind::InvalidData, "expected hash string")),
+    }
+}
+
+fn read_manifest(reader: impl io::Read, key: Option<[u8; 32]>) -> Result<Manifest, Error> {
+    match key {
+        Some(key) => Manifest::read_json_keyed(reader, key),
+        None => Manifest::read_json(reader),
+    }
+}
+
+fn read_key(filepath: &Path) -> Result<[u8; 32], Error> {
+    // raw 32 byte key, or 64 hexadecimal characters
+    let contents = fs::read(filepath)?;
+    if let Ok(key) = <[u8; 32]>::try_from(contents.as_slice()) {
+        return Ok(key);
+    }
+    std::str::from_utf8(&contents)
+        .ok()
+        .and_then(|hex| blake3::Hash::from_hex(hex.trim()).ok())
+        .map(|hash| *hash.as_bytes())
+        .ok_or_else(|| Error::new(
+            io::ErrorKind::InvalidData,
+            "key must be 32 bytes or 64 hexadecimal characters",
+        ))
+}
+
+fn key(matches: &ArgMatches) -> Option<[u8; 32]> {
+    matches.get_one::<PathBuf>("key-file").map(|filepath| {
+        read_key(filepath)
+            .unwrap_or_else(|e| exit_with_error(format!("{}: {e}", filepath.display()), EXIT_FAILURE))
+    })
+}
+
+fn source_arg(allow_stdin: bool) -> Arg {
+    let help = if allow_stdin {
+        format!("Sources to hash (filesystem paths or {SOURCE_STDIN} for tar stream on stdin)")
+    } else {
+        String::from("Source to hash (filesystem path)")
+    };
+    Arg::new("src")
+        .value_parser(PathBufferValueParser {
+            validate_exists: true,
+            allow_stdin,
+        })
+        .default_value(".")
+        .help(help)
+}
+
+fn read_patterns(filepath: &Path) -> Result<Vec<String>, Error> {
+    // one pattern per line, skipping blank lines and comments
+    Ok(fs::read_to_string(filepath)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_owned)
+        .collect())
+}
+
+fn hash_options_args() -> [Arg; 23] {
+    [
+        Arg::new("ignore-hidden")
+            .short('i')
+            .long("ignore-hidden")
+            .action(ArgAction::SetTrue)
+            .help("Ignore files or directories starting with dot or full stop"),
+        Arg::new("ignore-files")
+            .short('g')
+            .long("ignore-files")
+            .action(ArgAction::SetTrue)
+            .help("Ignore paths matching .gitignore, .ignore or .paqignore rules"),
+        Arg::new("global-ignore")
+            .long("global-ignore")
+            .action(ArgAction::SetTrue)
+            .requires("ignore-files")
+            .help("Also ignore paths matching global git excludes file of user (hashes may differ between systems)"),
+        Arg::new("include")
+            .long("include")
+            .value_name("PATTERN")
+            .action(ArgAction::Append)
+            .help("Only hash files matching glob pattern (relative path, or file name if no /)"),
+        Arg::new("exclude")
+            .long("exclude")
+            .value_name("PATTERN")
+            .action(ArgAction::Append)
+            .help("Skip paths matching glob pattern (relative path, or file name if no /)"),
+        Arg::new("exclude-from")
+            .long("exclude-from")
+            .value_name("FILE")
+            .value_parser(PathBufferValueParser {
+                validate_exists: true,
+                allow_stdin: false,
+            })
+            .action(ArgAction::Append)
+            .help("Skip paths matching glob patterns read from file (one per line)"),
+        Arg::new("follow-symlinks")
+            .short('L')
+            .long("follow-symlinks")
+            .action(ArgAction::SetTrue)
+            .help("Follow symlinks, hashing target contents instead of target path"),
+        Arg::new("dangling")
+            .long("dangling")
+            .value_name("POLICY")
+            .value_parser(["error", "skip", "hash"])
+            .default_value("error")
+            .requires("follow-symlinks")
+            .help("Handle followed symlinks with missing target (hash uses target path)"),
+        Arg::new("hash-version")
+            .long("hash-version")
+            .value_name("VERSION")
+            .value_parser(["1", "2"])
+            .default_value("1")
+            .help("Entry encoding (2 tags entry types and length-prefixes paths, prefixing hash with paq2:)"),
+        Arg::new("algorithm")
+            .long("algorithm")
+            .value_name("ALGORITHM")
+            .value_parser(["blake3", "sha256", "sha512", "xxh3"])
+            .default_value("blake3")
+            .help("Hashing algorithm (hash is prefixed with algorithm name unless blake3, e.g. sha256:)"),
+        Arg::new("key-file")
+            .long("key-file")
+            .value_name("FILE")
+            .value_parser(PathBufferValueParser {
+                validate_exists: true,
+                allow_stdin: false,
+            })
+            .conflicts_with("algorithm")
+            .help("Hash using keyed blake3 with key read from file (32 bytes or 64 hexadecimal characters)"),
+        Arg::new("length")
+            .long("length")
+            .value_name("N")
+            .value_parser(clap::value_parser!(u64).range(1..=paq::MAX_OUTPUT_LEN as u64))
+            .help("Output hash length in bytes, extended using blake3 XOF (default 32, at most 1024)"),
+        Arg::new("mode")
+            .long("mode")
+            .value_name("BITS")
+            .value_parser(["none", "exec", "full"])
+            .default_value("none")
+            .help("Hash file mode bits (exec only hashes whether any executable bit is set)"),
+        Arg::new("owner-ids")
+            .long("owner-ids")
+            .action(ArgAction::SetTrue)
+            .help("Hash owner user and group ids"),
+        Arg::new("owner-names")
+            .long("owner-names")
+            .action(ArgAction::SetTrue)
+            .help("Hash owner user and group names"),
+        Arg::new("mtime")
+            .long("mtime")
+            .value_name("GRANULARITY")
+            .value_parser(["s", "ms", "us", "ns"])
+            .help("Hash modification times truncated to granularity"),
+        Arg::new("xattrs")
+            .long("xattrs")
+            .action(ArgAction::SetTrue)
+            .help("Hash extended attributes, including capabilities, ACLs and SELinux labels"),
+        Arg::new("xattr-include")
+            .long("xattr-include")
+            .value_name("NAMESPACE")
+            .action(ArgAction::Append)
+            .requires("xattrs")
+            .help("Only hash extended attributes within namespace (e.g. user or security.capability)"),
+        Arg::new("xattr-exclude")
+            .long("xattr-exclude")
+            .value_name("NAMESPACE")
+            .action(ArgAction::Append)
+            .requires("xattrs")
+            .help("Skip extended attributes within namespace"),
+        Arg::new("archive")
+            .long("archive")
+            .action(ArgAction::SetTrue)
+            .help("Hash source file as tar (plain, gzip, zstd or xz) or zip archive, as if extracted"),
+        Arg::new("on-error")
+            .long("on-error")
+            .value_name("POLICY")
+            .value_parser(["fail", "skip", "record"])
+            .default_value("fail")
+            .help("Handle entries that cannot be read (record hashes typed error marker in place of entry)"),
+        Arg::new("cache")
+            .long("cache")
+            .value_name("FILE")
+            .value_parser(PathBufferValueParser {
+                validate_exists: false,
+                allow_stdin: false,
+            })
+            .help("Reuse digests of files unchanged since previous run, recorded in cache file outside source"),
+        Arg::new("threads")
+            .long("threads")
+            .value_name("N")
+            .env("PAQ_THREADS")
+            .value_parser(clap::value_parser!(usize))
+            .help("Number of hashing threads (1 hashes on main thread only) [default: number of CPUs]"),
+    ]
+}
+
+fn hash_options(matches: &ArgMatches) -> HashOptions {
+    let mut exclude: Vec<String> = matches
+        .get_many::<String>("exclude")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+    for filepath in matches.get_many::<PathBuf>("exclude-from").unwrap_or_default() {
+        let patterns = read_patterns(filepath)
+            .unwrap_or_else(|e| exit_with_error(format!("{}: {e}", filepath.display()), EXIT_FAILURE));
+        exclude.extend(patterns);
+    }
+    let mut options = HashOptions::new()
+        .ignore_hidden(matches.get_flag("ignore-hidden"))
+        .ignore_files(matches.get_flag("ignore-files"))
+        .global_ignore(matches.get_flag("global-ignore"))
+        .include(matches.get_many::<String>("include").unwrap_or_default().cloned())
+        .exclude(exclude)
+        .follow_symlinks(matches.get_flag("follow-symlinks"))
+        .dangling_symlinks(matches.get_one::<String>("dangling").unwrap().parse().unwrap())
+        .hash_version(matches.get_one::<String>("hash-version").unwrap().parse().unwrap())
+        .algorithm(matches.get_one::<String>("algorithm").unwrap().parse().unwrap())
+        .mode(matches.get_one::<String>("mode").unwrap().parse().unwrap())
+        .owner_ids(matches.get_flag("owner-ids"))
+        .owner_names(matches.get_flag("owner-names"))
+        .mtime(matches.get_one::<String>("mtime").map(|granularity| granularity.parse().unwrap()))
+        .xattrs(matches.get_flag("xattrs"))
+        .xattr_include(matches.get_many::<String>("xattr-include").unwrap_or_default().cloned())
+        .xattr_exclude(matches.get_many::<String>("xattr-exclude").unwrap_or_default().cloned())
+        .archive(matches.get_flag("archive"))
+        .on_error(matches.get_one::<String>("on-error").unwrap().parse().unwrap())
+        .cache(matches.get_one::<PathBuf>("cache").cloned());
+    if let Some(key) = key(matches) {
+        options = options.key(key);
+    }
+    if let Some(length) = matches.get_one::<u64>("length") {
+        options = options.length(*length as usize);
+    }
+    options
+}
+
+/// Hashing arguments given on command line that options recorded by a manifest take precedence over.
+///
+/// Key is still used to read keyed manifests, and cache file and threads do not affect hashes.
+fn overridden_hash_args(matches: &ArgMatches) -> Vec<String> {
+    hash_options_args()
+        .iter()
+        .filter(|arg| !matches!(arg.get_id().as_str(), "key-file" | "cache" | "threads"))
+        .filter(|arg| matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine))
+        .filter_map(|arg| arg.get_long().map(|long| format!("--{long}")))
+        .collect()
+}
+
+/// Threads of `--threads`, using global pool (sized to CPUs) if unset or 0.
+fn parallelism(matches: &ArgMatches) -> Parallelism {
+    match matches.get_one::<usize>("threads") {
+        None | Some(0) => Parallelism::Global,
+        Some(1) => Parallelism::Sequential,
+        Some(threads) => ThreadPoolBuilder::new()
+            .num_threads(*threads)
+            .build()
+            .map(|pool| Parallelism::Pool(Arc::new(pool)))
+            .unwrap_or_else(|e| exit_with_error(e, EXIT_FAILURE)),
+    }
+}
+
+fn hash(matches: &ArgMatches) {
+    let sources: Vec<&PathBuf> = matches.get_many::<PathBuf>("src").unwrap().collect();
+    let combine = matches.get_flag("combine");
+    let parallelism = parallelism(matches);
+    let mut options = hash_options(matches).parallelism(parallelism.clone());
+    // streams and archives cannot be counted without reading them
+    let scan = !matches.get_flag("tar")
+        && sources.iter().all(|source| {
+            source.as_path() != Path::new(SOURCE_STDIN) && !(matches.get_flag("archive") && source.is_file())
+        });
+    let progress = matches
+        .get_flag("progress")
+        .then(|| ProgressDisplay::start(&sources, &options, scan));
+    if let Some(progress) = &progress {
+        options = options.progress(Some(progress.counter.clone()));
+    }
+    if let ([source], false) = (sources.as_slice(), combine) {
+        hash_source(matches, &options, source, progress);
+    } else {
+        hash_sources(matches, &options, &parallelism, &sources, combine, progress);
+    }
+}
+
+/// Hash source from stdin or file as tar stream, or from file system.
+fn source_manifest(source: &Path, options: &HashOptions, tar: bool) -> Result<Manifest, paq::Error> {
+    if source == Path::new(SOURCE_STDIN) {
+        options.manifest_tar(io::stdin().lock())
+    } else if tar {
+        let file = File::open(source).map_err(|source| paq::Error::Open {
+            path: PathBuf::new(),
+            source,
+        })?;
+        options.manifest_tar(file)
+    } else {
+        options.manifest(source)
+    }
+}
+
+fn output_filepath(source: &Path, filepath: &Path) -> PathBuf {
+    match filepath.to_str() {
+        Some(s) if s == OUTPUT_DEFAULT => derive_output_filepath(source)
+            .unwrap_or_else(|e| exit_with_error(e, EXIT_FAILURE)),
+        _ => filepath.to_path_buf(),
+    }
+}
+
+fn hash_source(matches: &ArgMatches, options: &HashOptions, source: &Path, mut progress: Option<ProgressDisplay>) {
+    let output: Option<&PathBuf> = matches.get_one::<PathBuf>("filepath");
+    let manifest_output: Option<&PathBuf> = matches.get_one::<PathBuf>("manifest");
+    let tree = matches.get_flag("tree");
+    let stdin = source == Path::new(SOURCE_STDIN);
+    let tar = stdin || matches.get_flag("tar");
+    if stdin && output.is_some_and(|filepath| filepath == Path::new(OUTPUT_DEFAULT)) {
+        exit_with_error(
+            format!("output filepath must be given when reading source from {SOURCE_STDIN}"),
+            EXIT_FAILURE,
+        );
+    }
+    let format = matches.get_one::<String>("format").unwrap().as_str();
+    // listings need entry and content digests of manifest, tar streams are only hashed as manifest
+    let needs_manifest = manifest_output.is_some() || tree || tar || format == "checksum";
+    // reports and errors only need entries counted while hashing, without content digests
+    let needs_report = output.is_some() || format == "json" || catches_errors(matches);
+    let manifest = if needs_manifest {
+        let manifest = source_manifest(source, options, tar)
+            .unwrap_or_else(|e| exit_with_error(&e, exit_code(&e)));
+        // progress line is finished before output
+        if let Some(progress) = progress.take() {
+            progress.finish();
+        }
+        if let Some(manifest_filepath) = manifest_output {
+            write_manifest(manifest_filepath, &manifest)
+                .unwrap_or_else(|e| exit_with_error(e, EXIT_FAILURE));
+        }
+        if tree {
+            print_tree(&Tree::from_manifest(&manifest));
+        }
+        Some(manifest)
+    } else {
+        None
+    };
+    let (hash, report) = match &manifest {
+        Some(manifest) => (manifest.derive_root(), Some(Report::from_manifest(manifest))),
+        None => {
+            let (hash, report) = if needs_report {
+                let report = options
+                    .report(source)
+                    .unwrap_or_else(|e| exit_with_error(&e, exit_code(&e)));
+                (report.root().to_owned(), Some(report))
+            } else {
+                let hash = options
+                    .hash(source)
+                    .unwrap_or_else(|e| exit_with_error(&e, exit_code(&e)));
+                (hash, None)
+            };
+            if let Some(progress) = progress.take() {
+                progress.finish();
+            }
+            (hash, report)
+        }
+    };
+
+    if let (Some(filepath), Some(report)) = (output, &report) {
+        write_hashfile(&output_filepath(source, filepath), report)
+            .unwrap_or_else(|e| exit_with_error(e, EXIT_FAILURE));
+    }
+
+    // manifest written to stdout already contains root hash
+    if !manifest_output.is_some_and(|filepath| filepath == Path::new(MANIFEST_STDOUT)) {
+        let mut stdout = io::stdout().lock();
+        let written = match (format, &manifest, &report) {
+            ("json", _, Some(report)) => report.write_json(&mut stdout).and_then(|_| writeln!(stdout)),
+            ("checksum", Some(manifest), _) => manifest.write_checksums(&mut stdout, source),
+            _ => writeln!(stdout, "{hash}"),
+        };
+        written.unwrap_or_else(|e| exit_with_error(e, EXIT_FAILURE));
+    }
+    let errors = report.as_ref().map(Report::errors).unwrap_or_default();
+    report_errors(matches, errors.iter().map(|error| (source, error)).collect());
+}
+
+/// Entries that cannot be read are skipped or recorded instead of failing.
+fn catches_errors(matches: &ArgMatches) -> bool {
+    matches.get_one::<String>("on-error").is_some_and(|policy| policy != "fail")
+}
+
+/// Write error report to file if given, otherwise to stderr if any errors, exiting with distinct code if any errors.
+fn report_errors(matches: &ArgMatches, errors: Vec<(&Path, &EntryError)>) {
+    let written = match matches.get_one::<PathBuf>("error-report") {
+        Some(filepath) => File::create(filepath).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            paq::write_error_report(&mut writer, errors.iter().copied())?;
+            writeln!(writer)?;
+            writer.flush()
+        }),
+        None if !errors.is_empty() => {
+            let mut stderr = io::stderr().lock();
+            paq::write_error_report(&mut stderr, errors.iter().copied()).and_then(|_| writeln!(stderr))
+        }
+        None => Ok(()),
+    };
+    written.unwrap_or_else(|e| exit_with_error(e, EXIT_FAILURE));
+    if !errors.is_empty() {
+        process::exit(EXIT_PARTIAL);
+    }
+}
+
+/// Hash of one of several sources, with report if written to hash file or errors are caught.
+struct SourceHash {
+    hash: String,
+    report: Option<Report>,
+}
+
+/// Hash sources in parallel, printing hash labeled by source for each, then combined root if requested.
+fn hash_sources(
+    matches: &ArgMatches,
+    options: &HashOptions,
+    parallelism: &Parallelism,
+    sources: &[&PathBuf],
+    combine: bool,
+    progress: Option<ProgressDisplay>,
+) {
+    let output: Option<&PathBuf> = matches.get_one::<PathBuf>("filepath");
+    let single_source_only = matches.get_one::<PathBuf>("manifest").is_some()
+        || matches.get_flag("tree")
+        || matches.get_one::<String>("format").is_some_and(|format| format != "text");
+    if single_source_only {
+        exit_with_error("--manifest, --tree and --format require a single source", EXIT_FAILURE);
+    }
+    if output.is_some_and(|filepath| filepath != Path::new(OUTPUT_DEFAULT)) {
+        exit_with_error("output filepath cannot be given for multiple sources", EXIT_FAILURE);
+    }
+    // cache file records entries of one source, replaced by each run
+    if sources.len() > 1 && matches.get_one::<PathBuf>("cache").is_some() {
+        exit_with_error("--cache requires a single source", EXIT_FAILURE);
+    }
+    let stdin_count = sources.iter().filter(|source| source.as_path() == Path::new(SOURCE_STDIN)).count();
+    if stdin_count > 1 || (stdin_count == 1 && output.is_some()) {
+        exit_with_error(
+            format!("{SOURCE_STDIN} can be given once, without output filepath"),
+            EXIT_FAILURE,
+        );
+    }
+
+    let tar = matches.get_flag("tar");
+    let catches_errors = catches_errors(matches);
+    let hash = |source: &&PathBuf| {
+        let stdin = source.as_path() == Path::new(SOURCE_STDIN);
+        if stdin || tar {
+            let manifest = source_manifest(source, options, true)?;
+            Ok(SourceHash {
+                hash: manifest.derive_root(),
+                report: Some(Report::from_manifest(&manifest)),
+            })
+        } else if output.is_some() || catches_errors {
+            options.report(source).map(|report| SourceHash {
+                hash: report.root().to_owned(),
+                report: Some(report),
+            })
+        } else {
+            options.hash(source).map(|hash| SourceHash {
+                hash,
+                report: None,
+            })
+        }
+    };
+    // sources are hashed in parallel on same threads as their entries
+    let results: Vec<Result<SourceHash, paq::Error>> = match parallelism {
+        Parallelism::Global => sources.par_iter().map(hash).collect(),
+        Parallelism::Pool(pool) => pool.install(|| sources.par_iter().map(hash).collect()),
+        Parallelism::Sequential => sources.iter().map(hash).collect(),
+    };
+    if let Some(progress) = progress {
+        progress.finish();
+    }
+    let hashes: Vec<(&PathBuf, String, Option<Report>)> = sources
+        .iter()
+        .zip(results)
+        .map(|(source, result)| {
+            let SourceHash { hash, report } = result
+                .unwrap_or_else(|e| exit_with_error(format!("{}: {e}", source.display()), exit_code(&e)));
+            if let (Some(filepath), Some(report)) = (output, &report) {
+                write_hashfile(&output_filepath(source, filepath), report)
+                    .unwrap_or_else(|e| exit_with_error(e, EXIT_FAILURE));
+            }
+            (*source, hash, report)
+        })
+        .collect();
+
+    for (source, hash, _) in &hashes {
+        println!("{hash}  {}", source.display());
+    }
+    if combine {
+        println!("{}", options.combine(hashes.iter().map(|(source, hash, _)| (source, hash))));
+    }
+    report_errors(
+        matches,
+        hashes
+            .iter()
+            .flat_map(|(source, _, report)| {
+                report.iter().flat_map(|report| report.errors()).map(|error| (source.as_path(), error))
+            })
+            .collect(),
+    );
+}
+
+/// Files and bytes hashed, counted from worker threads.
+#[derive(Default)]
+struct ProgressCounter {
+    files: AtomicU64,
+    bytes: AtomicU64,
+    totals: OnceLock<ScanTotals>,
+}
+
+impl Progress for ProgressCounter {
+    fn entry_hashed(&self, kind: EntryKind, bytes: u64) {
+        if kind == EntryKind::File {
+            self.files.fetch_add(1, Ordering::Relaxed);
+        }
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+/// Progress line on stderr, redrawn until finished.
+///
+/// Totals for percentage and time remaining come from pre-scan of sources, run alongside hashing.
+struct ProgressDisplay {
+    counter: Arc<ProgressCounter>,
+    stop: mpsc::Sender<()>,
+    display: thread::JoinHandle<()>,
+}
+
+impl ProgressDisplay {
+    fn start(sources: &[&PathBuf], options: &HashOptions, scan: bool) -> Self {
+        let counter = Arc::new(ProgressCounter::default());
+        if scan {
+            let sources: Vec<PathBuf> = sources.iter().map(|source| source.to_path_buf()).collect();
+            let options = options.clone();
+            let counter = counter.clone();
+            thread::spawn(move || {
+                let totals = sources.iter().try_fold(ScanTotals::default(), |totals, source| {
+                    let scanned = options.scan(source)?;
+                    Ok::<_, paq::Error>(ScanTotals {
+                        files: totals.files + scanned.files,
+                        bytes: totals.bytes + scanned.bytes,
+                    })
+                });
+                if let Ok(totals) = totals {
+                    let _ = counter.totals.set(totals);
+                }
+            });
+        }
+
+        let (stop, stopped) = mpsc::channel();
+        let display = thread::spawn({
+            let counter = counter.clone();
+            move || {
+                let started = Instant::now();
+                // redraw in place on terminals, otherwise only write final line
+                let terminal = io::stderr().is_terminal();
+                let clear = if terminal { "\r\x1b[2K" } else { "" };
+                while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(Duration::from_millis(200)) {
+                    if terminal {
+                        eprint!("{clear}{}", progress_line(&counter, started.elapsed()));
+                    }
+                }
+                eprintln!("{clear}{}", progress_line(&counter, started.elapsed()));
+            }
+        });
+        ProgressDisplay { counter, stop, display }
+    }
+
+    fn finish(self) {
+        let _ = self.stop.send(());
+        let _ = self.display.join();
+    }
+}
+
+fn progress_line(counter: &ProgressCounter, elapsed: Duration) -> String {
+    let files = counter.files.load(Ordering::Relaxed);
+    let bytes = counter.bytes.load(Ordering::Relaxed);
+    let rate = bytes as f64 / elapsed.as_secs_f64().max(0.001);
+    match counter.totals.get() {
+        Some(totals) => {
+            let remaining = totals.bytes.saturating_sub(bytes) as f64 / rate.max(1.0);
+            format!(
+                "{files}/{} files, {}/{}, {}/s, ETA {}",
+                totals.files,
+                format_bytes(bytes as f64),
+                format_bytes(totals.bytes as f64),
+                format_bytes(rate),
+                format_duration(Duration::from_secs_f64(remaining.min(u32::MAX as f64))),
+            )
+        }
+        None => format!("{files} files, {}, {}/s", format_bytes(bytes as f64), format_bytes(rate)),
+    }
+}
+
+fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{value:.0} {}", UNITS[unit])
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, s) => format!("{s}s"),
+        (0, m, s) => format!("{m}m{s:02}s"),
+        (h, m, s) => format!("{h}h{m:02}m{s:02}s"),
+    }
+}
+
+/// Print Merkle digest of each directory, source root as `.`, depth-first.
+fn print_tree(tree: &Tree) {
+    for node in tree.iter().filter(|node| node.entry().kind == EntryKind::Dir) {
+        let path = match node.path() {
+            path if path.as_os_str().is_empty() => Path::new("."),
+            path => path,
+        };
+        println!("{}  {}", node.to_hex(), path.display());
+    }
+}
+
+fn verify(matches: &ArgMatches) {
+    let source = matches.get_one::<PathBuf>("src").unwrap();
+    let against = match matches.get_one::<PathBuf>("against") {
+        Some(filepath) => filepath.to_path_buf(),
+        None => derive_output_filepath(source)
+            .unwrap_or_else(|e| exit_with_error(e, EXIT_FAILURE)),
+    };
+    let (expected, recorded_options) = read_hashfile(&against, key(matches))
+        .unwrap_or_else(|e| exit_with_error(format!("{}: {e}", against.display()), EXIT_FAILURE));
+    // manifests record options used for hashing, which take precedence over arguments
+    let overridden = overridden_hash_args(matches);
+    if recorded_options.is_some() && !overridden.is_empty() {
+        eprintln!(
+            "warning: {} ignored, verifying using options recorded by {}",
+            overridden.join(", "),
+            against.display(),
+        );
+    }
+    let options = match recorded_options {
+        Some(options) => options.cache(matches.get_one::<PathBuf>("cache").cloned()),
+        None => hash_options(matches),
+    }
+    .parallelism(parallelism(matches));
+
+    let matched = options
+        .verify(source, &expected)
+        .unwrap_or_else(|e| exit_with_error(&e, exit_code(&e)));
+    if matched {
+        println!("{}: OK", source.display());
+    } else {
+        println!("{}: FAILED", source.display());
+        process::exit(EXIT_MISMATCH);
+    }
+}
+
+/// Saved manifest at path, detected by JSON content (anything else is hashed as source).
+///
+/// Exits if JSON is not a readable manifest, e.g. truncated, keyed without key, or a report without entries.
+fn saved_manifest(source: &Path, key: Option<[u8; 32]>) -> Option<Manifest> {
+    if !source.is_file() {
+        return None;
+    }
+    let contents = fs::read(source)
+        .unwrap_or_else(|e| exit_with_error(format!("{}: {e}", source.display()), EXIT_FAILURE));
+    // other JSON files, such as package.json, are hashed as sources
+    let is_report = match serde_json::from_slice::<serde_json::Value>(&contents) {
+        Ok(serde_json::Value::String(hash)) if is_root_hash(&hash) => true,
+        Ok(serde_json::Value::Object(object)) if is_saved_report(&object) => true,
+        Ok(serde_json::Value::Object(object)) if is_saved_manifest(&object) => false,
+        // truncated manifest is detected by root written as its first field
+        Err(e) if e.is_eof() && starts_with_root(&contents) => false,
+        _ => return None,
+    };
+    if is_report {
+        exit_with_error(
+            format!("{}: hash file has no entries, save manifest using --manifest to diff", source.display()),
+            EXIT_MANIFEST,
+        );
+    }
+    let manifest = read_manifest(contents.as_slice(), key)
+        .unwrap_or_else(|e| exit_with_error(format!("{}: {e}", source.display()), EXIT_MANIFEST));
+    Some(manifest)
+}
+
+fn is_root_hash(hash: &str) -> bool {
+    // hex digest, prefixed by hash version and algorithm name if not defaults
+    let digest = hash.rsplit(':').next().unwrap_or_default();
+    !digest.is_empty() && digest.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+fn is_saved_report(object: &serde_json::Map<String, serde_json::Value>) -> bool {
+    object.get("version").is_some_and(serde_json::Value::is_u64)
+        && object.get("root").is_some_and(serde_json::Value::is_string)
+        && object.get("entry_count").is_some_and(serde_json::Value::is_u64)
+}
+
+fn is_saved_manifest(object: &serde_json::Map<String, serde_json::Value>) -> bool {
+    object.get("root").is_some_and(serde_json::Value::is_string)
+        && object.get("options").is_some_and(serde_json::Value::is_object)
+        && object.get("entries").is_some_and(serde_json::Value::is_array)
+}
+
+fn starts_with_root(contents: &[u8]) -> bool {
+    contents
+        .trim_ascii_start()
+        .strip_prefix(b"{")
+        .is_some_and(|rest| rest.trim_ascii_start().starts_with(b"\"root\""))
+}
+
+fn diff(matches: &ArgMatches) {
+    let key = key(matches);
+    let old_source = matches.get_one::<PathBuf>("old").unwrap();
+    let new_source = matches.get_one::<PathBuf>("new").unwrap();
+    let old_saved = saved_manifest(old_source, key);
+    let new_saved = saved_manifest(new_source, key);
+    // sources compared to saved manifest are hashed using options it recorded, which take precedence over arguments
+    let options = match (&old_saved, &new_saved) {
+        (Some(manifest), _) | (_, Some(manifest)) => manifest
+            .options()
+            .clone()
+            .cache(matches.get_one::<PathBuf>("cache").cloned()),
+        (None, None) => hash_options(matches),
+    }
+    .parallelism(parallelism(matches));
+    let load = |saved: Option<Manifest>, source: &Path| {
+        saved.unwrap_or_else(|| {
+            options
+                .manifest(source)
+                .unwrap_or_else(|e| exit_with_error(&e, exit_code(&e)))
+        })
+    };
+    let old = load(old_saved, old_source);
+    let new = load(new_saved, new_source);
+    if !old.options().is_comparable(new.options()) {
+        exit_with_error(
+            "manifests differ in hash version, algorithm or key, so digests cannot be compared",
+            EXIT_FAILURE,
+        );
+    }
+
+    let diff = Diff::new(&old, &new);
+    let mut stdout = io::stdout().lock();
+    let written = if matches.get_flag("json") {
+        diff.write_json(&mut stdout).and_then(|_| writeln!(stdout))
+    } else {
+        write!(stdout, "{diff}")
+    };
+    written.unwrap_or_else(|e| exit_with_error(e, EXIT_FAILURE));
+
+    if !diff.is_empty() {
+        process::exit(EXIT_MISMATCH);
+    }
+}
+
+fn watch(matches: &ArgMatches) {
+    let source = matches.get_one::<PathBuf>("src").unwrap();
+    let options = hash_options(matches).parallelism(parallelism(matches));
+    let debounce = Duration::from_millis(*matches.get_one::<u64>("debounce").unwrap());
+    let exec = matches.get_one::<String>("exec");
+
+    let mut previous: Option<String> = None;
+    let result = options.watch(source, debounce, |event| {
+        match event {
+            Ok(event) if previous.as_deref() != Some(event.root()) => {
+                println!("{}", event.root());
+                // initial hash is not a change
+                if let (Some(command), Some(_)) = (exec, &previous) {
+                    run_command(command, event.root());
+                }
+                previous = Some(event.root().to_owned());
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("error: {e}"),
+        }
+        ControlFlow::Continue(())
+    });
+    if let Err(e) = result {
+        exit_with_error(&e, exit_code(&e));
+    }
 }
 
-fn write_hashfile(filepath: &PathBuf, hash: &str) -> Result<(), Error> {
-    let mut file = File::create(filepath).unwrap();
-    file.write_all(format!("\"{hash}\"").as_bytes())
+/// Run shell command to completion, reporting failure without exiting.
+fn run_command(command: &str, hash: &str) {
+    #[cfg(target_family = "windows")]
+    let mut shell = process::Command::new("cmd");
+    #[cfg(target_family = "windows")]
+    shell.arg("/C");
+    #[cfg(not(target_family = "windows"))]
+    let mut shell = process::Command::new("sh");
+    #[cfg(not(target_family = "windows"))]
+    shell.arg("-c");
+    match shell.arg(command).env("PAQ_HASH", hash).status() {
+        Ok(status) if !status.success() => eprintln!("error: command `{command}` failed: {status}"),
+        Ok(_) => {}
+        Err(e) => eprintln!("error: failed to run command `{command}`: {e}"),
+    }
 }
 
 fn main() {
-    let output_default = "<src>.paq";
     let matches = Command::new(crate_name!())
         .version(crate_version!())
         .about(crate_description!())
         .allow_external_subcommands(false)
+        .args_conflicts_with_subcommands(true)
+        .disable_help_subcommand(true)
+        .arg(source_arg(true).num_args(1..))
+        .args(hash_options_args())
         .arg(
-            Arg::new("src")
-                .value_parser(PathBufferValueParser {
-                    validate_exists: true,
-                })
-                .default_value(".")
-                .help("Source to hash (filesystem path)"),
-        )
-        .arg(
-            Arg::new("ignore-hidden")
-                .short('i')
-                .long("ignore-hidden")
+            Arg::new("tar")
+                .long("tar")
                 .action(ArgAction::SetTrue)
-                .help("Ignore files or directories starting with dot or full stop"),
+                .conflicts_with("archive")
+                .help(format!(
+                    "Read source as tar stream (plain, gzip, zstd or xz), implied by {SOURCE_STDIN}"
+                )),
         )
         .arg(
             Arg::new("filepath")
@@ -98,29 +1031,189 @@ fn main() {
                 .long("out")
                 .value_parser(PathBufferValueParser {
                     validate_exists: false,
+                    allow_stdin: false,
+                })
+                .require_equals(true)
+                .num_args(0..=1)
+                .default_missing_value(OUTPUT_DEFAULT)
+                .help(format!(
+                    "Output hash (filesystem path) [default: {OUTPUT_DEFAULT}]"
+                )),
+        )
+        .arg(
+            Arg::new("manifest")
+                .short('m')
+                .long("manifest")
+                .value_parser(PathBufferValueParser {
+                    validate_exists: false,
+                    allow_stdin: false,
                 })
                 .require_equals(true)
                 .num_args(0..=1)
-                .default_missing_value(output_default)
+                .default_missing_value(MANIFEST_STDOUT)
                 .help(format!(
-                    "Output hash (filesystem path) [default: {output_default}]"
+                    "Output per-entry manifest as JSON (filesystem path or {MANIFEST_STDOUT} for stdout) [default: {MANIFEST_STDOUT}]"
                 )),
         )
-        .after_help("Fails if operating system denies read access to any source file.")
+        .arg(
+            Arg::new("tree")
+                .long("tree")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("manifest")
+                .help("Output Merkle digest of each directory before root hash"),
+        )
+        .arg(
+            Arg::new("progress")
+                .long("progress")
+                .action(ArgAction::SetTrue)
+                .help("Display files and bytes hashed, throughput and time remaining on stderr"),
+        )
+        .arg(
+            Arg::new("combine")
+                .long("combine")
+                .action(ArgAction::SetTrue)
+                .help("Output root over hashes labeled by source after hash of each source"),
+        )
+        .arg(
+            Arg::new("error-report")
+                .long("error-report")
+                .value_name("FILE")
+                .value_parser(PathBufferValueParser {
+                    validate_exists: false,
+                    allow_stdin: false,
+                })
+                .help("Write JSON report of entries skipped or recorded by --on-error to file [default: stderr]"),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_parser(["text", "json", "checksum"])
+                .default_value("text")
+                .conflicts_with("tree")
+                .help("Output root hash as text, JSON report, or content digest of each file (b3sum --check compatible)"),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Verify source matches hash saved by --out")
+                .arg(source_arg(false))
+                .args(hash_options_args())
+                .arg(
+                    Arg::new("against")
+                        .short('a')
+                        .long("against")
+                        .value_parser(PathBufferValueParser {
+                            validate_exists: true,
+                            allow_stdin: false,
+                        })
+                        .help(format!(
+                            "Hash file or manifest to verify against, using options recorded by manifest (filesystem path) [default: {OUTPUT_DEFAULT}]"
+                        )),
+                )
+                .after_help(
+                    "Hash version and algorithm are detected from hash prefix, so hash files of any version or algorithm can be verified.\n\n\
+                    Exit codes:\n  \
+                    0  source matches hash\n  \
+                    1  source does not match hash\n  \
+                    2  invalid arguments\n  \
+                    3+ failed to read hash file or hash source (see paq --help)",
+                ),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Compare entries of two sources or saved manifests")
+                .arg(
+                    Arg::new("old")
+                        .required(true)
+                        .value_parser(PathBufferValueParser {
+                            validate_exists: true,
+                            allow_stdin: false,
+                        })
+                        .help("Old source or manifest (filesystem path)"),
+                )
+                .arg(
+                    Arg::new("new")
+                        .required(true)
+                        .value_parser(PathBufferValueParser {
+                            validate_exists: true,
+                            allow_stdin: false,
+                        })
+                        .help("New source or manifest (filesystem path)"),
+                )
+                .args(hash_options_args())
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .help("Output changes as JSON"),
+                )
+                .after_help(
+                    "Lists added (A), removed (D), modified (M), type changed (T) and renamed (R) paths.\n\
+                    Files that are saved manifests (see --manifest) are compared without hashing, and sources compared\n\
+                    to them are hashed using options they recorded. Manifests of different hash version, algorithm or key\n\
+                    cannot be compared. Hash files (see --out) cannot be diffed, and other files are hashed as sources.\n\n\
+                    Exit codes:\n  \
+                    0  no differences\n  \
+                    1  differences found\n  \
+                    2  invalid arguments\n  \
+                    3+ failed to hash source (see paq --help)\n  \
+                    18 failed to read saved manifest",
+                ),
+        )
+        .subcommand(
+            Command::new("watch")
+                .about("Output hash of source whenever changes settle")
+                .arg(source_arg(false))
+                .args(hash_options_args())
+                .arg(
+                    Arg::new("debounce")
+                        .long("debounce")
+                        .value_name("MS")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("200")
+                        .help("Milliseconds without changes before hashing again"),
+                )
+                .arg(
+                    Arg::new("exec")
+                        .long("exec")
+                        .value_name("COMMAND")
+                        .help("Run shell command when hash changes, with new hash in PAQ_HASH environment variable"),
+                )
+                .after_help(
+                    "Outputs initial hash, then new hash whenever it changes. Only changed paths are walked and hashed again.\n\
+                    Errors while hashing changes are reported and watching continues.\n\n\
+                    Exit codes:\n  \
+                    2  invalid arguments\n  \
+                    3+ failed to hash or watch source (see paq --help)",
+                ),
+        )
+        .after_help(
+            "Fails if operating system denies read access to any source file, unless --on-error is skip or record.\n\n\
+            Exit codes:\n  \
+            0  success\n  \
+            2  invalid arguments\n  \
+            3  other failure, such as writing output\n  \
+            4  failed to traverse directory\n  \
+            5  failed to open file\n  \
+            6  failed to read file\n  \
+            7  failed to read metadata\n  \
+            8  failed to read symlink\n  \
+            9  path is not valid Unicode (Windows)\n  \
+            10 failed to read ignore file\n  \
+            11 invalid include or exclude pattern\n  \
+            12 directory loop detected\n  \
+            13 followed symlink target does not exist\n  \
+            14 failed to read extended attributes\n  \
+            15 failed to watch source\n  \
+            16 archive entry cannot be hashed as if extracted\n  \
+            17 entries skipped or recorded by --on-error (hash is output)\n  \
+            18 failed to read saved manifest (diff)",
+        )
         .get_matches();
 
-    let source = matches.get_one::<PathBuf>("src").unwrap();
-    let ignore_hidden = matches.get_flag("ignore-hidden");
-    let output: Option<&PathBuf> = matches.get_one::<PathBuf>("filepath");
-    let hash = hash_source(source, ignore_hidden);
-
-    if let Some(filepath) = output {
-        let output_filepath = match filepath.to_str().unwrap() {
-            s if s == output_default => derive_output_filepath(source),
-            _ => filepath.to_path_buf(),
-        };
-        write_hashfile(&output_filepath, hash.as_str()).unwrap();
+    match matches.subcommand() {
+        Some(("verify", matches)) => verify(matches),
+        Some(("diff", matches)) => diff(matches),
+        Some(("watch", matches)) => watch(matches),
+        _ => hash(&matches),
     }
-
-    println!("{hash}");
 }
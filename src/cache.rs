@@ -0,0 +1,265 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::{
+        self,
+        File,
+        Metadata,
+    },
+    io::{
+        self,
+        BufReader,
+        BufWriter,
+        Write,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::Mutex,
+    time::{
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+
+use super::{
+    digest::{
+        from_hex,
+        to_hex,
+        Digest,
+    },
+    error::Error,
+    manifest::{
+        escape_path,
+        unescape_path,
+    },
+    options::HashOptions,
+};
+
+
+/// Version of cache file format.
+const CACHE_VERSION: u64 = 1;
+
+/// Files changed this close to start of hashing run are not cached (nanoseconds).
+///
+/// Writes within timestamp granularity of file system may not change mtime, so such files are
+/// hashed again next run.
+const RACY_NANOS: i64 = 1_000_000_000;
+
+/// File system metadata identifying unchanged file content.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct FileStat {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime: i64,
+    ctime: i64,
+}
+
+impl FileStat {
+    pub(crate) fn size(&self) -> u64 {
+        self.size
+    }
+
+    #[cfg(target_family = "unix")]
+    pub(crate) fn new(metadata: &Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+
+        FileStat {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            size: metadata.size(),
+            mtime: metadata.mtime().saturating_mul(1_000_000_000).saturating_add(metadata.mtime_nsec()),
+            ctime: metadata.ctime().saturating_mul(1_000_000_000).saturating_add(metadata.ctime_nsec()),
+        }
+    }
+
+    /// Without inode or change time, files are identified by size and modification time.
+    #[cfg(not(target_family = "unix"))]
+    pub(crate) fn new(metadata: &Metadata) -> Self {
+        let mtime = metadata.modified().map(unix_nanos).unwrap_or(i64::MAX);
+        FileStat {
+            dev: 0,
+            ino: 0,
+            size: metadata.len(),
+            mtime,
+            ctime: mtime,
+        }
+    }
+}
+
+struct CacheEntry {
+    stat: FileStat,
+    content: Option<Digest>,
+    digest: Digest,
+}
+
+/// Content and entry digests of files from previous hashing run, keyed by path relative to source root.
+///
+/// Cache file records options affecting entry digests (and a check of key, not key itself),
+/// so changing hash version, algorithm, key or metadata selection starts an empty cache.
+/// Only entries hashed during current run are written back, so removed files are pruned.
+/// Content digests are only recorded when building manifests, so that hashing hashes file contents once.
+pub(crate) struct Cache {
+    path: PathBuf,
+    options: Value,
+    started: i64,
+    entries: HashMap<PathBuf, CacheEntry>,
+    fresh: Mutex<Vec<(PathBuf, CacheEntry)>>,
+}
+
+impl Cache {
+    /// Load cache file, starting empty if missing, malformed or written using other options.
+    pub(crate) fn load(path: &Path, options: &HashOptions) -> Self {
+        let cache_options = cache_options(options);
+        let entries = File::open(path)
+            .ok()
+            .and_then(|file| serde_json::from_reader::<_, Value>(BufReader::new(file)).ok())
+            .filter(|value| {
+                value.get("version").and_then(Value::as_u64) == Some(CACHE_VERSION)
+                    && value.get("options") == Some(&cache_options)
+            })
+            .and_then(|value| read_entries(&value, options))
+            .unwrap_or_default();
+        Cache {
+            path: path.to_path_buf(),
+            options: cache_options,
+            started: unix_nanos(SystemTime::now()),
+            entries,
+            fresh: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Content and entry digests of file if unchanged since cached, and content digest was cached if required.
+    pub(crate) fn get(&self, path: &Path, stat: &FileStat, with_content: bool) -> Option<(Option<Digest>, Digest)> {
+        self.entries
+            .get(path)
+            .filter(|entry| entry.stat == *stat && (!with_content || entry.content.is_some()))
+            .map(|entry| (entry.content.clone(), entry.digest.clone()))
+    }
+
+    /// Record digests of file, unless changed too recently to detect later writes.
+    pub(crate) fn insert(&self, path: PathBuf, stat: FileStat, content: Option<Digest>, digest: Digest) {
+        let racy = self.started.saturating_sub(RACY_NANOS);
+        if stat.mtime >= racy || stat.ctime >= racy {
+            return;
+        }
+        let mut fresh = self.fresh.lock().unwrap_or_else(|e| e.into_inner());
+        fresh.push((path, CacheEntry { stat, content, digest }));
+    }
+
+    /// Replace cache file with entries recorded during current run.
+    pub(crate) fn save(self) -> Result<(), Error> {
+        let mut fresh = self.fresh.into_inner().unwrap_or_else(|e| e.into_inner());
+        fresh.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        let entries: Vec<Value> = fresh
+            .iter()
+            .map(|(path, entry)| {
+                json!({
+                    "path": escape_path(path),
+                    "dev": entry.stat.dev,
+                    "ino": entry.stat.ino,
+                    "size": entry.stat.size,
+                    "mtime": entry.stat.mtime,
+                    "ctime": entry.stat.ctime,
+                    "content": entry.content.as_deref().map(to_hex),
+                    "digest": to_hex(&entry.digest),
+                })
+            })
+            .collect();
+        let value = json!({
+            "version": CACHE_VERSION,
+            "options": self.options,
+            "entries": entries,
+        });
+        write_atomic(&self.path, &value).map_err(|source| Error::Cache {
+            path: self.path.clone(),
+            source,
+        })
+    }
+}
+
+/// Options affecting entry digests, with check of key so that cache of other key is not used.
+fn cache_options(options: &HashOptions) -> Value {
+    let mut value = options.to_json();
+    if let Some(object) = value.as_object_mut() {
+        // walk options, error handling and output length do not affect entry digests
+        for field in [
+            "ignore_hidden",
+            "ignore_files",
+            "global_ignore",
+            "include",
+            "exclude",
+            "follow_symlinks",
+            "dangling_symlinks",
+            "archive",
+            "on_error",
+            "length",
+        ] {
+            object.remove(field);
+        }
+        if options.key.is_some() {
+            let mut hasher = options.hasher();
+            hasher.update(b"paq cache key check");
+            object.insert("key_check".to_owned(), Value::String(to_hex(&hasher.finalize())));
+        }
+    }
+    value
+}
+
+fn read_entries(value: &Value, options: &HashOptions) -> Option<HashMap<PathBuf, CacheEntry>> {
+    let len = options.algorithm.output_len();
+    value
+        .get("entries")?
+        .as_array()?
+        .iter()
+        .map(|entry| {
+            let get_u64 = |field: &str| entry.get(field).and_then(Value::as_u64);
+            let get_i64 = |field: &str| entry.get(field).and_then(Value::as_i64);
+            let get_digest = |field: &str| entry.get(field).and_then(Value::as_str).and_then(|hex| from_hex(hex, len));
+            let path = unescape_path(entry.get("path")?.as_str()?).ok()?;
+            let stat = FileStat {
+                dev: get_u64("dev")?,
+                ino: get_u64("ino")?,
+                size: get_u64("size")?,
+                mtime: get_i64("mtime")?,
+                ctime: get_i64("ctime")?,
+            };
+            let content = match entry.get("content")? {
+                Value::Null => None,
+                _ => Some(get_digest("content")?),
+            };
+            Some((path, CacheEntry {
+                stat,
+                content,
+                digest: get_digest("digest")?,
+            }))
+        })
+        .collect()
+}
+
+/// Write JSON to temporary file beside path, then rename over path.
+fn write_atomic(path: &Path, value: &Value) -> io::Result<()> {
+    let mut temp_name = path.file_name().map(OsString::from).unwrap_or_default();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    serde_json::to_writer(&mut writer, value)?;
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&temp_path, path)
+}
+
+/// Nanoseconds since Unix epoch (negative before epoch), saturating.
+fn unix_nanos(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => i64::try_from(duration.as_nanos()).unwrap_or(i64::MAX),
+        Err(e) => i64::try_from(e.duration().as_nanos()).map_or(i64::MIN, |nanos| -nanos),
+    }
+}
@@ -0,0 +1,232 @@
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+        VecDeque,
+    },
+    fmt,
+    io::{
+        self,
+        prelude::*,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+
+use super::{
+    error::DisplayPath,
+    manifest::{
+        escape_path,
+        Entry,
+        EntryKind,
+        Manifest,
+    },
+};
+
+
+/// Difference of single path between two manifests.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    /// Path only exists in new manifest.
+    Added(Entry),
+    /// Path only exists in old manifest.
+    Removed(Entry),
+    /// Path exists in both manifests with same type and different digest.
+    Modified { old: Entry, new: Entry },
+    /// Path exists in both manifests with different type.
+    TypeChanged { old: Entry, new: Entry },
+    /// Path moved without changing type or content.
+    Renamed { old: Entry, new: Entry },
+}
+
+impl Change {
+    /// Path of change within new manifest, or old manifest if removed.
+    pub fn path(&self) -> &Path {
+        match self {
+            Change::Added(entry) | Change::Removed(entry) => &entry.path,
+            Change::Modified { new, .. }
+            | Change::TypeChanged { new, .. }
+            | Change::Renamed { new, .. } => &new.path,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        match self {
+            Change::Added(entry) => json!({
+                "change": "added",
+                "path": escape_path(&entry.path),
+                "type": entry.kind.as_str(),
+            }),
+            Change::Removed(entry) => json!({
+                "change": "removed",
+                "path": escape_path(&entry.path),
+                "type": entry.kind.as_str(),
+            }),
+            Change::Modified { new, .. } => json!({
+                "change": "modified",
+                "path": escape_path(&new.path),
+                "type": new.kind.as_str(),
+            }),
+            Change::TypeChanged { old, new } => json!({
+                "change": "type_changed",
+                "path": escape_path(&new.path),
+                "old_type": old.kind.as_str(),
+                "type": new.kind.as_str(),
+            }),
+            Change::Renamed { old, new } => json!({
+                "change": "renamed",
+                "old_path": escape_path(&old.path),
+                "path": escape_path(&new.path),
+                "type": new.kind.as_str(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added(entry) => write!(f, "A {}", DisplayPath(&entry.path)),
+            Change::Removed(entry) => write!(f, "D {}", DisplayPath(&entry.path)),
+            Change::Modified { new, .. } => write!(f, "M {}", DisplayPath(&new.path)),
+            Change::TypeChanged { old, new } => {
+                write!(f, "T {} ({} -> {})", DisplayPath(&new.path), old.kind, new.kind)
+            }
+            Change::Renamed { old, new } => {
+                write!(f, "R {} -> {}", DisplayPath(&old.path), DisplayPath(&new.path))
+            }
+        }
+    }
+}
+
+/// Differences between two manifests sorted by path.
+///
+/// Renames are detected by matching content digests of removed and added files or symlinks. Digests are
+/// only meaningful between manifests of comparable options (see [`HashOptions::is_comparable`](super::HashOptions::is_comparable)).
+///
+/// ```
+/// use paq;
+///
+/// let source = std::path::PathBuf::from("example");
+/// let old = paq::hash_source_with_manifest(&source, true).unwrap();
+/// let new = paq::hash_source_with_manifest(&source, false).unwrap();
+///
+/// let diff = paq::Diff::new(&old, &new);
+/// assert!(diff.changes().iter().all(|change| matches!(change, paq::Change::Added(_))));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diff {
+    changes: Vec<Change>,
+}
+
+impl Diff {
+    /// Compare old manifest to new manifest.
+    ///
+    /// Source root is displayed as `.`.
+    pub fn new(old: &Manifest, new: &Manifest) -> Self {
+        let old_entries: BTreeMap<&PathBuf, &Entry> = old.entries()
+            .iter()
+            .map(|entry| (&entry.path, entry))
+            .collect();
+        let new_entries: BTreeMap<&PathBuf, &Entry> = new.entries()
+            .iter()
+            .map(|entry| (&entry.path, entry))
+            .collect();
+
+        let mut changes = Vec::new();
+        let mut removed = Vec::new();
+        for (path, old_entry) in &old_entries {
+            match new_entries.get(path) {
+                Some(new_entry) if new_entry.kind != old_entry.kind => {
+                    changes.push(Change::TypeChanged {
+                        old: (*old_entry).clone(),
+                        new: (*new_entry).clone(),
+                    });
+                }
+                Some(new_entry) if new_entry.digest != old_entry.digest => {
+                    changes.push(Change::Modified {
+                        old: (*old_entry).clone(),
+                        new: (*new_entry).clone(),
+                    });
+                }
+                Some(_) => {}
+                None => removed.push(*old_entry),
+            }
+        }
+
+        // index added entries by content for rename detection (directories have no content, errors are not moved)
+        let mut added: HashMap<(EntryKind, &[u8]), VecDeque<&Entry>> = HashMap::new();
+        let mut added_dirs = Vec::new();
+        for (path, new_entry) in &new_entries {
+            if old_entries.contains_key(path) {
+                continue;
+            }
+            if matches!(new_entry.kind, EntryKind::Dir | EntryKind::Error) {
+                added_dirs.push(*new_entry);
+            } else {
+                added
+                    .entry((new_entry.kind, new_entry.content.as_slice()))
+                    .or_default()
+                    .push_back(*new_entry);
+            }
+        }
+
+        for old_entry in removed {
+            let renamed = added
+                .get_mut(&(old_entry.kind, old_entry.content.as_slice()))
+                .and_then(VecDeque::pop_front);
+            match renamed {
+                Some(new_entry) => changes.push(Change::Renamed {
+                    old: old_entry.clone(),
+                    new: new_entry.clone(),
+                }),
+                None => changes.push(Change::Removed(old_entry.clone())),
+            }
+        }
+        changes.extend(
+            added
+                .into_values()
+                .flatten()
+                .chain(added_dirs)
+                .map(|entry| Change::Added(entry.clone())),
+        );
+
+        changes.sort_by(|a, b| a.path().cmp(b.path()));
+        Diff { changes }
+    }
+
+    /// Changes sorted by path.
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    /// Manifests contain same entries.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Write changes as JSON.
+    ///
+    /// Paths are escaped as in [`Manifest::write_json`].
+    pub fn write_json<W: Write>(&self, writer: W) -> io::Result<()> {
+        let changes: Vec<Value> = self.changes.iter().map(Change::to_json).collect();
+        serde_json::to_writer_pretty(writer, &json!({ "changes": changes }))?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{change}")?;
+        }
+        Ok(())
+    }
+}
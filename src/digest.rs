@@ -0,0 +1,328 @@
+use std::{
+    fmt::{
+        self,
+        Write,
+    },
+    str::FromStr,
+    sync::Arc,
+};
+
+use arrayvec::ArrayVec;
+use sha2::Digest as _;
+use xxhash_rust::xxh3::Xxh3;
+
+
+/// Maximum digest length in bytes.
+pub const MAX_DIGEST_LEN: usize = 64;
+
+/// Maximum root hash length in bytes, see [`HashOptions::length`](crate::HashOptions::length).
+pub const MAX_OUTPUT_LEN: usize = 1024;
+
+/// Digest bytes with length given by [`Algorithm::output_len`].
+pub type Digest = ArrayVec<u8, MAX_DIGEST_LEN>;
+
+/// Digest algorithm pluggable into [`HashOptions`](crate::HashOptions) using [`Algorithm::custom`].
+///
+/// Built-in algorithms implement it through [`Algorithm`].
+///
+/// # Example
+///
+/// ```
+/// use paq::{Algorithm, Digest, DigestAlgorithm, DigestHasher, HashOptions};
+///
+/// struct Truncated;
+///
+/// struct TruncatedHasher(Box<dyn DigestHasher>);
+///
+/// impl DigestAlgorithm for Truncated {
+///     fn name(&self) -> &str {
+///         "sha512-128"
+///     }
+///
+///     fn output_len(&self) -> usize {
+///         16
+///     }
+///
+///     fn hasher(&self) -> Box<dyn DigestHasher> {
+///         Box::new(TruncatedHasher(Algorithm::Sha512.hasher()))
+///     }
+/// }
+///
+/// impl DigestHasher for TruncatedHasher {
+///     fn update(&mut self, bytes: &[u8]) {
+///         self.0.update(bytes);
+///     }
+///
+///     fn finalize(self: Box<Self>) -> Digest {
+///         self.0.finalize().into_iter().take(16).collect()
+///     }
+/// }
+///
+/// let source = std::env::temp_dir().join("paq-custom-digest-example");
+/// std::fs::write(&source, b"example").unwrap();
+/// let hash = HashOptions::new().algorithm(Algorithm::custom(Truncated)).hash(&source).unwrap();
+/// assert!(hash.starts_with("sha512-128:"));
+/// assert_eq!(hash.len(), "sha512-128:".len() + 32);
+/// ```
+pub trait DigestAlgorithm: Send + Sync {
+    /// Name recorded in options and used as root hash prefix, e.g. `sha256`.
+    fn name(&self) -> &str;
+
+    /// Digest length in bytes, at most [`MAX_DIGEST_LEN`].
+    fn output_len(&self) -> usize;
+
+    /// Create incremental hasher.
+    fn hasher(&self) -> Box<dyn DigestHasher>;
+}
+
+/// Incremental hasher created by [`DigestAlgorithm::hasher`].
+pub trait DigestHasher: Send {
+    /// Feed bytes.
+    fn update(&mut self, bytes: &[u8]);
+
+    /// Digest of fed bytes with length given by [`DigestAlgorithm::output_len`].
+    fn finalize(self: Box<Self>) -> Digest;
+}
+
+/// Hashing algorithm used for entry digests and source root.
+#[derive(Clone, Default)]
+pub enum Algorithm {
+    /// BLAKE3 with 256-bit output.
+    #[default]
+    Blake3,
+    /// SHA-256.
+    Sha256,
+    /// SHA-512.
+    Sha512,
+    /// XXH3 with 128-bit output (not cryptographic).
+    Xxh3,
+    /// Custom digest, identified by name.
+    ///
+    /// Name must not be empty, contain `:` or whitespace, or equal name of built-in algorithm or `blake3-keyed`,
+    /// and output length must be 1 to [`MAX_DIGEST_LEN`] bytes; hashing fails with
+    /// [`Error::UnsupportedAlgorithm`](crate::Error::UnsupportedAlgorithm) otherwise. Options recorded with custom
+    /// algorithm (e.g. in manifests) cannot be read back, since only name is recorded.
+    Custom(Arc<dyn DigestAlgorithm>),
+}
+
+impl Algorithm {
+    /// Custom digest algorithm.
+    pub fn custom(algorithm: impl DigestAlgorithm + 'static) -> Self {
+        Algorithm::Custom(Arc::new(algorithm))
+    }
+
+    /// Name used in options and hash output.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Algorithm::Blake3 => "blake3",
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Sha512 => "sha512",
+            Algorithm::Xxh3 => "xxh3",
+            Algorithm::Custom(algorithm) => algorithm.name(),
+        }
+    }
+
+    /// Digest length in bytes.
+    pub fn output_len(&self) -> usize {
+        match self {
+            Algorithm::Blake3 | Algorithm::Sha256 => 32,
+            Algorithm::Sha512 => 64,
+            Algorithm::Xxh3 => 16,
+            Algorithm::Custom(algorithm) => algorithm.output_len(),
+        }
+    }
+
+    /// Prefix of root hash string (empty for default algorithm).
+    pub fn prefix(&self) -> String {
+        match self {
+            Algorithm::Blake3 => String::new(),
+            _ => format!("{}:", self.as_str()),
+        }
+    }
+
+    /// Detect algorithm and keyed mode from prefix of root hash string following version prefix.
+    pub(crate) fn from_label(label: &str) -> (Self, bool) {
+        match label.split_once(':').map(|(name, _)| name) {
+            Some(KEYED_NAME) => (Algorithm::Blake3, true),
+            Some(name) => (name.parse().unwrap_or_default(), false),
+            None => (Algorithm::default(), false),
+        }
+    }
+
+    /// Custom algorithm can be named in options and hash output without being mistaken for another.
+    pub(crate) fn is_valid(&self) -> bool {
+        match self {
+            Algorithm::Custom(algorithm) => {
+                let name = algorithm.name();
+                !name.is_empty()
+                    && !name.contains(|c: char| c == ':' || c.is_whitespace())
+                    && name != KEYED_NAME
+                    && name.parse::<Algorithm>().is_err()
+                    && (1..=MAX_DIGEST_LEN).contains(&algorithm.output_len())
+            }
+            _ => true,
+        }
+    }
+
+    /// Digest of bytes.
+    pub fn digest(&self, bytes: &[u8]) -> Digest {
+        let mut hasher = Hasher::new(self, None);
+        hasher.update(bytes);
+        hasher.finalize()
+    }
+}
+
+impl DigestAlgorithm for Algorithm {
+    fn name(&self) -> &str {
+        self.as_str()
+    }
+
+    fn output_len(&self) -> usize {
+        Algorithm::output_len(self)
+    }
+
+    fn hasher(&self) -> Box<dyn DigestHasher> {
+        Box::new(Hasher::new(self, None))
+    }
+}
+
+impl fmt::Debug for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Algorithm::Blake3 => f.write_str("Blake3"),
+            Algorithm::Sha256 => f.write_str("Sha256"),
+            Algorithm::Sha512 => f.write_str("Sha512"),
+            Algorithm::Xxh3 => f.write_str("Xxh3"),
+            Algorithm::Custom(algorithm) => f.debug_tuple("Custom").field(&algorithm.name()).finish(),
+        }
+    }
+}
+
+impl PartialEq for Algorithm {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Algorithm::Custom(a), Algorithm::Custom(b)) => a.name() == b.name() && a.output_len() == b.output_len(),
+            (Algorithm::Custom(_), _) | (_, Algorithm::Custom(_)) => false,
+            _ => self.as_str() == other.as_str(),
+        }
+    }
+}
+
+impl Eq for Algorithm {}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blake3" => Ok(Algorithm::Blake3),
+            "sha256" => Ok(Algorithm::Sha256),
+            "sha512" => Ok(Algorithm::Sha512),
+            "xxh3" => Ok(Algorithm::Xxh3),
+            _ => Err(format!("unknown algorithm `{s}`")),
+        }
+    }
+}
+
+/// Name of keyed BLAKE3 used as root hash string prefix.
+pub(crate) const KEYED_NAME: &str = "blake3-keyed";
+
+/// Secret key for keyed BLAKE3, never written to output.
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct Key(pub(crate) [u8; 32]);
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+/// Incremental hasher of [`Algorithm`].
+///
+/// Built-in variants are unboxed to avoid allocation per entry; BLAKE3 dominates size.
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum Hasher {
+    Blake3(blake3::Hasher),
+    Sha256(sha2::Sha256),
+    Sha512(sha2::Sha512),
+    Xxh3(Xxh3),
+    Custom(Box<dyn DigestHasher>),
+}
+
+impl Hasher {
+    /// Create hasher, keyed if key is given (only supported by BLAKE3 and checked by caller).
+    pub(crate) fn new(algorithm: &Algorithm, key: Option<&Key>) -> Self {
+        match algorithm {
+            Algorithm::Blake3 => Hasher::Blake3(match key {
+                Some(key) => blake3::Hasher::new_keyed(&key.0),
+                None => blake3::Hasher::new(),
+            }),
+            Algorithm::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            Algorithm::Sha512 => Hasher::Sha512(sha2::Sha512::new()),
+            Algorithm::Xxh3 => Hasher::Xxh3(Xxh3::new()),
+            Algorithm::Custom(algorithm) => Hasher::Custom(algorithm.hasher()),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Hasher::Blake3(hasher) => { hasher.update(bytes); },
+            Hasher::Sha256(hasher) => hasher.update(bytes),
+            Hasher::Sha512(hasher) => hasher.update(bytes),
+            Hasher::Xxh3(hasher) => hasher.update(bytes),
+            Hasher::Custom(hasher) => hasher.update(bytes),
+        }
+    }
+
+    /// Finalize to output of length, extended using XOF for BLAKE3 (length is checked by caller).
+    pub(crate) fn finalize_xof(self, length: usize) -> Vec<u8> {
+        match self {
+            Hasher::Blake3(hasher) => {
+                let mut output = vec![0; length];
+                hasher.finalize_xof().fill(&mut output);
+                output
+            }
+            hasher => hasher.finalize().to_vec(),
+        }
+    }
+
+    pub(crate) fn finalize(self) -> Digest {
+        match self {
+            Hasher::Blake3(hasher) => hasher.finalize().as_bytes().iter().copied().collect(),
+            Hasher::Sha256(hasher) => hasher.finalize().into_iter().collect(),
+            Hasher::Sha512(hasher) => hasher.finalize().into_iter().collect(),
+            Hasher::Xxh3(hasher) => hasher.digest128().to_be_bytes().into_iter().collect(),
+            Hasher::Custom(hasher) => hasher.finalize(),
+        }
+    }
+}
+
+impl DigestHasher for Hasher {
+    fn update(&mut self, bytes: &[u8]) {
+        Hasher::update(self, bytes);
+    }
+
+    fn finalize(self: Box<Self>) -> Digest {
+        Hasher::finalize(*self)
+    }
+}
+
+/// Lowercase hexadecimal encoding of digest.
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{byte:02x}");
+    }
+    hex
+}
+
+/// Decode hexadecimal digest of expected length.
+pub(crate) fn from_hex(hex: &str, len: usize) -> Option<Digest> {
+    if hex.len() != len * 2 || len > MAX_DIGEST_LEN {
+        return None;
+    }
+    (0..len)
+        .map(|i| hex.get(i * 2..i * 2 + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
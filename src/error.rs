@@ -0,0 +1,307 @@
+use std::{
+    error,
+    fmt,
+    io::{
+        self,
+        Write,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    str::FromStr,
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+
+use super::{
+    digest::{
+        Algorithm,
+        MAX_DIGEST_LEN,
+    },
+    manifest::escape_path,
+};
+
+
+/// Failure while hashing file system source.
+///
+/// Variants carry the offending path relative to source root (empty for source root itself).
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Failed to traverse directory.
+    Walk { path: PathBuf, source: io::Error },
+    /// Failed to open file.
+    Open { path: PathBuf, source: io::Error },
+    /// Failed to read file contents.
+    Read { path: PathBuf, source: io::Error },
+    /// Failed to read file system metadata.
+    Metadata { path: PathBuf, source: io::Error },
+    /// Failed to read symlink target.
+    ReadLink { path: PathBuf, source: io::Error },
+    /// Path cannot be encoded for hashing (Windows paths that are not valid Unicode).
+    PathEncoding { path: PathBuf },
+    /// Failed to read extended attributes.
+    Xattr { path: PathBuf, source: io::Error },
+    /// Failed to read or parse ignore file.
+    Ignore { path: PathBuf, source: io::Error },
+    /// Directory is its own ancestor through symlink or bind mount.
+    Loop { path: PathBuf },
+    /// Followed symlink target does not exist.
+    DanglingSymlink { path: PathBuf },
+    /// Invalid include or exclude glob pattern.
+    Pattern { pattern: String, source: globset::Error },
+    /// Keyed hashing is not supported by algorithm.
+    UnsupportedKey { algorithm: Algorithm },
+    /// Output length is not supported by algorithm.
+    UnsupportedLength { algorithm: Algorithm, length: usize },
+    /// Custom algorithm name or digest length is not supported (see [`Algorithm::Custom`]).
+    UnsupportedAlgorithm { name: String },
+    /// Failed to write cache file.
+    Cache { path: PathBuf, source: io::Error },
+    /// Failed to watch source for changes.
+    Watch { path: PathBuf, source: io::Error },
+    /// Archive entry cannot be hashed as if extracted (empty path for unsupported options).
+    Archive { path: PathBuf, reason: String },
+}
+
+impl Error {
+    /// Name of error type used in error reports, e.g. `read`.
+    pub fn kind_name(&self) -> &'static str {
+        match self {
+            Error::Walk { .. } => "walk",
+            Error::Open { .. } => "open",
+            Error::Read { .. } => "read",
+            Error::Metadata { .. } => "metadata",
+            Error::ReadLink { .. } => "read_link",
+            Error::PathEncoding { .. } => "path_encoding",
+            Error::Xattr { .. } => "xattr",
+            Error::Ignore { .. } => "ignore",
+            Error::Loop { .. } => "loop",
+            Error::DanglingSymlink { .. } => "dangling_symlink",
+            Error::Pattern { .. } => "pattern",
+            Error::UnsupportedKey { .. } => "unsupported_key",
+            Error::UnsupportedLength { .. } => "unsupported_length",
+            Error::UnsupportedAlgorithm { .. } => "unsupported_algorithm",
+            Error::Cache { .. } => "cache",
+            Error::Watch { .. } => "watch",
+            Error::Archive { .. } => "archive",
+        }
+    }
+
+    /// Error reading single entry below source root, which [`OnError`] can skip or record.
+    pub(crate) fn is_entry_error(&self) -> bool {
+        let is_entry = matches!(
+            self,
+            Error::Walk { .. }
+                | Error::Open { .. }
+                | Error::Read { .. }
+                | Error::Metadata { .. }
+                | Error::ReadLink { .. }
+                | Error::PathEncoding { .. }
+                | Error::Xattr { .. }
+                | Error::Ignore { .. }
+                | Error::Loop { .. }
+                | Error::DanglingSymlink { .. }
+        );
+        is_entry && !self.path().as_os_str().is_empty()
+    }
+
+    /// Path relative to source root that caused error (empty for invalid options).
+    pub fn path(&self) -> &Path {
+        match self {
+            Error::Pattern { .. }
+            | Error::UnsupportedKey { .. }
+            | Error::UnsupportedLength { .. }
+            | Error::UnsupportedAlgorithm { .. } => Path::new(""),
+            Error::Walk { path, .. }
+            | Error::Open { path, .. }
+            | Error::Read { path, .. }
+            | Error::Metadata { path, .. }
+            | Error::ReadLink { path, .. }
+            | Error::Xattr { path, .. }
+            | Error::PathEncoding { path }
+            | Error::Loop { path }
+            | Error::DanglingSymlink { path }
+            | Error::Ignore { path, .. }
+            | Error::Cache { path, .. }
+            | Error::Watch { path, .. }
+            | Error::Archive { path, .. } => path,
+        }
+    }
+}
+
+/// Display path relative to source root, using `.` for source root itself.
+pub(crate) struct DisplayPath<'a>(pub(crate) &'a Path);
+
+impl fmt::Display for DisplayPath<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.as_os_str().is_empty() {
+            write!(f, ".")
+        } else {
+            write!(f, "{}", self.0.display())
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = DisplayPath(self.path());
+        match self {
+            Error::Walk { source, .. } => write!(f, "failed to traverse {path}: {source}"),
+            Error::Open { source, .. } => write!(f, "failed to open {path}: {source}"),
+            Error::Read { source, .. } => write!(f, "failed to read {path}: {source}"),
+            Error::Metadata { source, .. } => write!(f, "failed to read metadata of {path}: {source}"),
+            Error::ReadLink { source, .. } => write!(f, "failed to read symlink {path}: {source}"),
+            Error::Xattr { source, .. } => write!(f, "failed to read extended attributes of {path}: {source}"),
+            Error::PathEncoding { .. } => write!(f, "failed to encode path {path}: not valid UTF-8"),
+            Error::Ignore { source, .. } => write!(f, "failed to read ignore file {path}: {source}"),
+            Error::Cache { source, .. } => write!(f, "failed to write cache file {path}: {source}"),
+            Error::Watch { source, .. } => write!(f, "failed to watch {path}: {source}"),
+            Error::Archive { path, reason } if path.as_os_str().is_empty() => {
+                write!(f, "failed to hash archive: {reason}")
+            }
+            Error::Archive { reason, .. } => write!(f, "failed to hash archive entry {path}: {reason}"),
+            Error::Loop { .. } => write!(f, "failed to traverse {path}: directory loop detected"),
+            Error::DanglingSymlink { .. } => write!(f, "failed to follow symlink {path}: target does not exist"),
+            Error::Pattern { pattern, source } => write!(f, "invalid pattern `{pattern}`: {source}"),
+            Error::UnsupportedKey { algorithm } => {
+                write!(f, "keyed hashing requires blake3 algorithm, not {}", algorithm.as_str())
+            }
+            Error::UnsupportedLength { algorithm, length } => {
+                write!(f, "output length of {length} bytes is not supported by {} algorithm", algorithm.as_str())
+            }
+            Error::UnsupportedAlgorithm { name } => write!(
+                f,
+                "custom algorithm `{name}` is not supported: name must be unique without `:` or whitespace \
+                 and digest length 1 to {MAX_DIGEST_LEN} bytes",
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Walk { source, .. }
+            | Error::Open { source, .. }
+            | Error::Read { source, .. }
+            | Error::Metadata { source, .. }
+            | Error::ReadLink { source, .. }
+            | Error::Xattr { source, .. }
+            | Error::Ignore { source, .. }
+            | Error::Cache { source, .. }
+            | Error::Watch { source, .. } => Some(source),
+            Error::Pattern { source, .. } => Some(source),
+            Error::PathEncoding { .. }
+            | Error::UnsupportedKey { .. }
+            | Error::UnsupportedLength { .. }
+            | Error::UnsupportedAlgorithm { .. }
+            | Error::Archive { .. }
+            | Error::Loop { .. }
+            | Error::DanglingSymlink { .. } => None,
+        }
+    }
+}
+
+/// Handling of entries below source root that cannot be read, e.g. due to permissions.
+///
+/// Errors of source root itself and invalid options always fail. Archives and tar streams always fail.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnError {
+    /// Fail with error of first entry.
+    #[default]
+    Fail,
+    /// Skip entry, hashing as if it did not exist.
+    Skip,
+    /// Hash typed error marker in place of entry, so root differs from any run without errors.
+    Record,
+}
+
+impl OnError {
+    /// Name used in options output.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OnError::Fail => "fail",
+            OnError::Skip => "skip",
+            OnError::Record => "record",
+        }
+    }
+
+    /// Error is skipped or recorded instead of failing.
+    pub(crate) fn catches(&self, error: &Error) -> bool {
+        *self != OnError::Fail && error.is_entry_error()
+    }
+}
+
+impl FromStr for OnError {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(OnError::Fail),
+            "skip" => Ok(OnError::Skip),
+            "record" => Ok(OnError::Record),
+            _ => Err(format!("unknown error policy `{s}`")),
+        }
+    }
+}
+
+/// Entry skipped or recorded in place of hashing, see [`OnError`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntryError {
+    /// Path relative to source root.
+    pub path: PathBuf,
+    /// Error type, see [`Error::kind_name`].
+    pub kind: &'static str,
+    /// Error message.
+    pub message: String,
+}
+
+impl From<&Error> for EntryError {
+    fn from(error: &Error) -> Self {
+        EntryError {
+            path: error.path().to_path_buf(),
+            kind: error.kind_name(),
+            message: error.to_string(),
+        }
+    }
+}
+
+/// Write errors of entries skipped or recorded while hashing sources as JSON error report.
+///
+/// Each error is paired with its source. Paths are escaped as by [`Manifest::write_json`](super::Manifest::write_json).
+///
+/// ```
+/// use paq::EntryError;
+///
+/// let error = EntryError {
+///     path: "secret.txt".into(),
+///     kind: "open",
+///     message: "failed to open secret.txt: Permission denied".to_owned(),
+/// };
+/// let mut report = Vec::new();
+/// paq::write_error_report(&mut report, [(std::path::Path::new("example"), &error)]).unwrap();
+///
+/// assert!(String::from_utf8(report).unwrap().contains(r#""error": "open""#));
+/// ```
+pub fn write_error_report<'a, W, I>(writer: W, errors: I) -> io::Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = (&'a Path, &'a EntryError)>,
+{
+    let errors: Vec<Value> = errors
+        .into_iter()
+        .map(|(source, error)| json!({
+            "source": escape_path(source),
+            "path": escape_path(&error.path),
+            "error": error.kind,
+            "message": error.message,
+        }))
+        .collect();
+    serde_json::to_writer_pretty(writer, &json!({ "errors": errors }))?;
+    Ok(())
+}
@@ -1,17 +1,101 @@
+mod archive;
+mod cache;
+mod diff;
+mod digest;
+mod error;
+mod manifest;
+mod metadata;
+mod options;
+mod progress;
+mod report;
+mod tree;
+mod walk;
+mod watch;
+
 use std::{
-    fs,
+    collections::HashSet,
+    fs::{
+        self,
+        FileType,
+    },
     io::prelude::*,
     iter,
-    path::Path,
+    path::{
+        Path,
+        PathBuf,
+    },
 };
 
 pub use arrayvec::ArrayString;
-use blake3::Hasher;
 use memmap2::Mmap;
 use rayon::prelude::*;
-use walkdir::{
-    DirEntry,
-    WalkDir,
+
+use archive::{
+    archive_entries,
+    tar_entries,
+};
+use cache::{
+    Cache,
+    FileStat,
+};
+pub use diff::{
+    Change,
+    Diff,
+};
+pub use digest::{
+    Algorithm,
+    Digest,
+    DigestAlgorithm,
+    DigestHasher,
+    MAX_DIGEST_LEN,
+    MAX_OUTPUT_LEN,
+};
+use digest::{
+    to_hex,
+    Hasher,
+};
+pub use error::{
+    write_error_report,
+    EntryError,
+    Error,
+    OnError,
+};
+pub use manifest::{
+    Entry,
+    EntryKind,
+    Manifest,
+};
+pub use metadata::{
+    ModeBits,
+    TimeGranularity,
+};
+use metadata::MetadataEncoder;
+pub use options::{
+    HashOptions,
+    HashVersion,
+    Parallelism,
+};
+pub use progress::{
+    Progress,
+    ScanTotals,
+};
+pub use report::{
+    Report,
+    REPORT_VERSION,
+};
+pub use tree::{
+    Tree,
+    TreeNode,
+};
+pub use walk::{
+    DanglingSymlinks,
+    IGNORE_FILE_NAMES,
+};
+pub use watch::WatchEvent;
+use walk::{
+    relative_path,
+    WalkEntry,
+    Walker,
 };
 
 
@@ -26,100 +110,308 @@ pub const FILE_BUFFER_SIZE: usize = 32 * 1024;
 #[cfg(target_os = "windows")]
 pub const FILE_BUFFER_SIZE: usize = 128 * 1024;
 
-#[inline]
-fn is_hidden(entry: &DirEntry) -> bool {
-    entry
-        .file_name()
-        .to_str()
-        .map(|s| s != "." && s.starts_with("."))
-        .unwrap_or(false)
+/// Hashers fed with entry data.
+///
+/// Content hasher is only tracked when building a manifest.
+struct EntryHasher {
+    hasher: Hasher,
+    content: Option<Hasher>,
+    size: u64,
 }
 
-#[inline]
-fn filter(ignore_hidden: bool) -> impl FnMut(&DirEntry) -> bool {
-    if ignore_hidden {
-        |entry: &DirEntry| -> bool { !is_hidden(entry) }
-    } else {
-        |_: &DirEntry| -> bool { true }
+impl EntryHasher {
+    fn new(options: &HashOptions, with_content: bool) -> Self {
+        EntryHasher {
+            hasher: options.hasher(),
+            content: with_content.then(|| options.hasher()),
+            size: 0,
+        }
+    }
+
+    /// Feed relative path, prefixed by entry type tag and path length for version 2.
+    #[inline]
+    fn update_path(&mut self, bytes: &[u8], kind: EntryKind, hash_version: HashVersion) {
+        if hash_version == HashVersion::V2 {
+            self.hasher.update(&[kind.tag()]);
+            self.hasher.update(&(bytes.len() as u64).to_le_bytes());
+        }
+        self.hasher.update(bytes);
+    }
+
+    #[inline]
+    fn update_metadata(&mut self, bytes: &[u8]) {
+        self.hasher.update(bytes);
+    }
+
+    #[inline]
+    fn update_content(&mut self, bytes: &[u8]) {
+        self.hasher.update(bytes);
+        self.size += bytes.len() as u64;
+        if let Some(content) = self.content.as_mut() {
+            content.update(bytes);
+        }
+    }
+
+    fn finish(self, path: PathBuf, kind: EntryKind, options: &HashOptions) -> Entry {
+        Entry {
+            path,
+            kind,
+            size: self.size,
+            content: self
+                .content
+                .unwrap_or_else(|| options.hasher())
+                .finalize(),
+            digest: self.hasher.finalize(),
+        }
     }
 }
 
-fn buffer_file_to_hasher(hasher: &mut Hasher, path: &Path) {
-    let mut file = fs::File::open(path).unwrap();
+/// Entry recording error in place of entry.
+///
+/// Digest covers error type tag, length-prefixed path and error type regardless of hash version, so it
+/// cannot equal digest of any readable entry; content is digest of error type.
+fn error_entry(error: &EntryError, options: &HashOptions) -> Entry {
+    let mut hasher = EntryHasher::new(options, true);
+    hasher.update_path(error.path.as_os_str().as_encoded_bytes(), EntryKind::Error, HashVersion::V2);
+    hasher.update_content(error.kind.as_bytes());
+    Entry {
+        size: 0,
+        ..hasher.finish(error.path.clone(), EntryKind::Error, options)
+    }
+}
+
+/// Entries recording errors, if options record errors.
+fn error_entries<'a>(errors: &'a [EntryError], options: &'a HashOptions) -> impl Iterator<Item = Entry> + 'a {
+    errors
+        .iter()
+        .filter(move |_| options.on_error == OnError::Record)
+        .map(move |error| error_entry(error, options))
+}
+
+fn buffer_file_to_hasher(hasher: &mut EntryHasher, file: &mut fs::File, relative: &Path) -> Result<(), Error> {
     let mut buffer = [0; FILE_BUFFER_SIZE];
     loop {
-        let buffer_size = file.read(&mut buffer[..]).unwrap();
+        let buffer_size = file.read(&mut buffer[..]).map_err(|source| Error::Read {
+            path: relative.to_path_buf(),
+            source,
+        })?;
         if buffer_size == 0 { break; }
-        hasher.update(&buffer[..buffer_size]);
+        hasher.update_content(&buffer[..buffer_size]);
     }
+    Ok(())
 }
 
-fn hash_path(root: &Path, entry: &DirEntry) -> [u8; 32] {
-    let path = entry.path();
-    let source_path = path.strip_prefix(root).unwrap().to_str().unwrap();
-    let source_type = entry.file_type();
-
-    let mut hasher = Hasher::new();
-    // hash paths for fs changes other than file content (must be relative to root)
+/// Path relative to root using `/` separators.
+///
+/// Unix paths are kept as raw `OsStr` bytes; Windows paths must be valid Unicode.
+fn source_path(root: &Path, entry: &WalkEntry) -> Result<PathBuf, Error> {
+    let relative = relative_path(root, entry.path());
     #[cfg(target_family = "unix")]
     {
-        hasher.update(source_path.as_bytes());
+        Ok(relative.to_path_buf())
     }
     #[cfg(target_family = "windows")]
     {
-        hasher.update(source_path.replace("\\", "/").as_bytes());
+        let source_path = relative.to_str().ok_or_else(|| Error::PathEncoding {
+            path: relative.to_path_buf(),
+        })?;
+        Ok(PathBuf::from(source_path.replace("\\", "/")))
+    }
+}
+
+fn entry_kind(source_type: FileType) -> EntryKind {
+    if source_type.is_symlink() {
+        EntryKind::Symlink
+    } else if source_type.is_dir() {
+        EntryKind::Dir
+    } else {
+        EntryKind::File
+    }
+}
+
+fn hash_path(
+    relative: &Path,
+    entry: &WalkEntry,
+    hash_version: HashVersion,
+    metadata: &MetadataEncoder,
+    hasher: &mut EntryHasher,
+) -> Result<(), Error> {
+    let path = entry.path();
+    let source_type = entry.file_type();
+
+    // hash paths for fs changes other than file content (must be relative to root)
+    // raw bytes are identical to UTF-8 encoding for valid Unicode paths
+    hasher.update_path(relative.as_os_str().as_encoded_bytes(), entry_kind(source_type), hash_version);
+    if !metadata.is_empty() {
+        // selected metadata precedes content
+        let entry_metadata = entry.metadata().map_err(|source| Error::Metadata {
+            path: relative.to_path_buf(),
+            source,
+        })?;
+        hasher.update_metadata(&metadata.encode(&entry_metadata));
+        let xattrs = metadata
+            .encode_xattrs(path, source_type.is_symlink())
+            .map_err(|source| Error::Xattr {
+                path: relative.to_path_buf(),
+                source,
+            })?;
+        hasher.update_metadata(&xattrs);
     }
     if source_type.is_symlink() {
         // for symlinks add hash of target path
-        let symlink_target = fs::read_link(path).unwrap();
+        let symlink_target = fs::read_link(path).map_err(|source| Error::ReadLink {
+            path: relative.to_path_buf(),
+            source,
+        })?;
         #[cfg(target_family = "unix")]
         {
-            hasher.update(symlink_target.to_str().unwrap().as_bytes());
+            hasher.update_content(symlink_target.as_os_str().as_encoded_bytes());
         }
         #[cfg(target_family = "windows")]
         {
-            hasher.update(
-                symlink_target
-                    .to_str()
-                    .unwrap()
-                    .replace("\\", "/")
-                    .as_bytes(),
-            );
+            let symlink_target = symlink_target.to_str().ok_or_else(|| Error::PathEncoding {
+                path: relative.to_path_buf(),
+            })?;
+            hasher.update_content(symlink_target.replace("\\", "/").as_bytes());
         }
     } else if source_type.is_file() {
         // for files, add contents to hasher
-        let metadata = entry.metadata().unwrap();
+        let metadata = entry.metadata().map_err(|source| Error::Metadata {
+            path: relative.to_path_buf(),
+            source,
+        })?;
         let file_size = metadata.len();
         if file_size == 0 {
             // empty file, return immediately
-            return *hasher.finalize().as_bytes();
-        } else if file_size < MAX_FILE_SIZE_FOR_UNBUFFERED_READ {
+            return Ok(());
+        }
+        let mut file = fs::File::open(path).map_err(|source| Error::Open {
+            path: relative.to_path_buf(),
+            source,
+        })?;
+        if file_size < MAX_FILE_SIZE_FOR_UNBUFFERED_READ {
             // small file read using unbuffered
-            let file = fs::read(path).unwrap();
-            hasher.update(&file);
+            let mut contents = Vec::with_capacity(file_size as usize);
+            file.read_to_end(&mut contents).map_err(|source| Error::Read {
+                path: relative.to_path_buf(),
+                source,
+            })?;
+            hasher.update_content(&contents);
         } else if file_size > MIN_FILE_SIZE_FOR_MMAP_READ {
             // large size files read using mmap or fail to buffered read
-            let file = fs::File::open(path).unwrap();
             match unsafe { Mmap::map(&file) } {
-                Ok(mmap) => { hasher.update(&mmap); },
-                Err(_) => { buffer_file_to_hasher(&mut hasher, path) ; },
+                Ok(mmap) => { hasher.update_content(&mmap); },
+                Err(_) => { buffer_file_to_hasher(hasher, &mut file, relative)?; },
             }
         } else {
             // medium file size read using buffer
-            buffer_file_to_hasher(&mut hasher, path);
+            buffer_file_to_hasher(hasher, &mut file, relative)?;
         }
     }
-    *hasher.finalize().as_bytes()
+    Ok(())
 }
 
-fn get_hashes_root(file_hashes: Vec<[u8; 32]>) -> ArrayString<64> {
-    let mut flattened_bytes = Vec::with_capacity(file_hashes.len() * 32);
+fn hash_entry(
+    root: &Path,
+    entry: &WalkEntry,
+    options: &HashOptions,
+    metadata: &MetadataEncoder,
+    cache: Option<&Cache>,
+) -> Result<Digest, Error> {
+    if cache.is_some() {
+        return cached_entry(root, entry, options, metadata, cache, false).map(|entry| entry.digest);
+    }
+    let source_path = source_path(root, entry)?;
+    let mut hasher = EntryHasher::new(options, false);
+    hash_path(&source_path, entry, options.hash_version, metadata, &mut hasher)?;
+    options.entry_hashed(entry_kind(entry.file_type()), hasher.size);
+    Ok(hasher.hasher.finalize())
+}
 
-    for file_hash in &file_hashes {
-        flattened_bytes.extend_from_slice(file_hash);
+fn manifest_entry(
+    root: &Path,
+    entry: &WalkEntry,
+    options: &HashOptions,
+    metadata: &MetadataEncoder,
+    cache: Option<&Cache>,
+) -> Result<Entry, Error> {
+    cached_entry(root, entry, options, metadata, cache, true)
+}
+
+/// Hash entry, reusing digests of unchanged files recorded by cache.
+///
+/// Content is fed to entry and content hashers in a single read. Without `with_content`, content digest
+/// is not computed, so entry content is digest of nothing unless cached.
+fn cached_entry(
+    root: &Path,
+    entry: &WalkEntry,
+    options: &HashOptions,
+    metadata: &MetadataEncoder,
+    cache: Option<&Cache>,
+    with_content: bool,
+) -> Result<Entry, Error> {
+    let source_path = source_path(root, entry)?;
+    let kind = entry_kind(entry.file_type());
+    // files are identified in cache by metadata read before hashing
+    let cached = match cache {
+        Some(cache) if kind == EntryKind::File => Some((cache, file_stat(&source_path, entry)?)),
+        _ => None,
+    };
+    if let Some((cache, stat)) = cached {
+        if let Some((content, digest)) = cache.get(&source_path, &stat, with_content) {
+            cache.insert(source_path.clone(), stat, content.clone(), digest.clone());
+            options.entry_hashed(kind, stat.size());
+            return Ok(Entry {
+                path: source_path,
+                kind,
+                size: stat.size(),
+                content: content.unwrap_or_else(|| options.hasher().finalize()),
+                digest,
+            });
+        }
     }
 
-    blake3::hash(&flattened_bytes).to_hex()
+    let mut hasher = EntryHasher::new(options, with_content);
+    hash_path(&source_path, entry, options.hash_version, metadata, &mut hasher)?;
+    let manifest_entry = hasher.finish(source_path, kind, options);
+    options.entry_hashed(kind, manifest_entry.size);
+    if let Some((cache, stat)) = cached {
+        // files changed while hashing are not cached
+        if file_stat(&manifest_entry.path, entry)? == stat {
+            cache.insert(
+                manifest_entry.path.clone(),
+                stat,
+                with_content.then(|| manifest_entry.content.clone()),
+                manifest_entry.digest.clone(),
+            );
+        }
+    }
+    Ok(manifest_entry)
+}
+
+fn file_stat(relative: &Path, entry: &WalkEntry) -> Result<FileStat, Error> {
+    let metadata = entry.metadata().map_err(|source| Error::Metadata {
+        path: relative.to_path_buf(),
+        source,
+    })?;
+    Ok(FileStat::new(&metadata))
+}
+
+/// Cache of options, unless owner names are hashed (names can change without files).
+fn load_cache(options: &HashOptions) -> Option<Cache> {
+    match options.cache.as_deref() {
+        Some(path) if !options.owner_names => Some(Cache::load(path, options)),
+        _ => None,
+    }
+}
+
+/// Digest of sorted entry digests concatenated, using options output length.
+fn get_hashes_root(file_hashes: &[Digest], options: &HashOptions) -> Vec<u8> {
+    let mut hasher = options.hasher();
+    for file_hash in file_hashes {
+        hasher.update(file_hash);
+    }
+    hasher.finalize_xof(options.output_len())
 }
 
 /// Hash file system source.
@@ -137,38 +429,235 @@ fn get_hashes_root(file_hashes: Vec<[u8; 32]>) -> ArrayString<64> {
 ///
 /// assert_eq!(&source_hash[..], "d7d25c9b2fdb7391e650085a985ad0d892c7f0dd5edd32c7ccdb4b0d1c34c430");
 /// ```
+///
+/// # Panics
+///
+/// Panics if source cannot be hashed. See [`try_hash_source`] for fallible variant.
 pub fn hash_source(source: &Path, ignore_hidden: bool) -> ArrayString<64> {
-    // construct file system walker
-    let mut walker = WalkDir::new(source)
-        .follow_links(false)
-        .into_iter()
-        .filter_entry(filter(ignore_hidden));
+    try_hash_source(source, ignore_hidden).unwrap_or_else(|e| panic!("Critical: {e}"))
+}
+
+/// Hash file system source, returning error instead of panicking.
+///
+/// Source **must** be a path to a file or directory.
+///
+/// Uses `blake3` hashing algorithm. Equivalent to [`HashOptions::hash`] with `ignore_hidden` set.
+///
+/// ```
+/// use paq;
+///
+/// let source = std::path::PathBuf::from("example");
+/// let ignore_hidden = true;
+/// let source_hash = paq::try_hash_source(&source, ignore_hidden).unwrap();
+///
+/// assert_eq!(&source_hash[..], "d7d25c9b2fdb7391e650085a985ad0d892c7f0dd5edd32c7ccdb4b0d1c34c430");
+///
+/// let missing = std::path::PathBuf::from("example/missing");
+/// assert!(matches!(paq::try_hash_source(&missing, ignore_hidden), Err(paq::Error::Walk { .. })));
+/// ```
+pub fn try_hash_source(source: &Path, ignore_hidden: bool) -> Result<ArrayString<64>, Error> {
+    let root = hash_with_options(source, &HashOptions::new().ignore_hidden(ignore_hidden))?;
+    Ok(ArrayString::from(&to_hex(&root)).expect("blake3 root is 64 hexadecimal characters"))
+}
+
+/// Hash file system source, returning root hash with per-entry digests.
+///
+/// Source **must** be a path to a file or directory.
+///
+/// Uses `blake3` hashing algorithm. Equivalent to [`HashOptions::manifest`] with `ignore_hidden` set.
+///
+/// ```
+/// use paq;
+///
+/// let source = std::path::PathBuf::from("example");
+/// let ignore_hidden = true;
+/// let manifest = paq::hash_source_with_manifest(&source, ignore_hidden).unwrap();
+///
+/// assert_eq!(manifest.root(), "d7d25c9b2fdb7391e650085a985ad0d892c7f0dd5edd32c7ccdb4b0d1c34c430");
+/// assert_eq!(manifest.root(), manifest.derive_root().as_str());
+/// ```
+pub fn hash_source_with_manifest(source: &Path, ignore_hidden: bool) -> Result<Manifest, Error> {
+    HashOptions::new()
+        .ignore_hidden(ignore_hidden)
+        .manifest(source)
+}
 
+/// Verify file system source matches expected hash.
+///
+/// Source **must** be a path to a file or directory.
+///
+/// Uses default [`HashOptions`]; see [`HashOptions::verify`] for further configuration.
+///
+/// ```
+/// use paq;
+///
+/// let source = std::path::PathBuf::from("example");
+/// let expected = paq::hash_source(&source, false);
+///
+/// assert!(paq::verify_source(&source, &expected).unwrap());
+/// assert!(!paq::verify_source(&source, "d7d25c9b2fdb7391e650085a985ad0d892c7f0dd5edd32c7ccdb4b0d1c34c430").unwrap());
+/// ```
+pub fn verify_source(source: &Path, expected: &str) -> Result<bool, Error> {
+    HashOptions::new().verify(source, expected)
+}
+
+/// Hash entries of source, returning errors of entries that options skip or record (see [`OnError`]).
+///
+/// Entries are dropped if their path also failed, e.g. directory that could not be listed.
+fn hash_entries<T, F>(source: &Path, options: &HashOptions, hash: F) -> Result<(Vec<T>, Vec<EntryError>), Error>
+where
+    T: Send,
+    F: Fn(&Path, &WalkEntry) -> Result<T, Error> + Sync,
+{
+    hash_walk(source, Walker::new(source, options)?, options, hash)
+}
+
+/// Hash entries yielded by walker of source, see [`hash_entries`].
+fn hash_walk<T, F>(
+    source: &Path,
+    mut walker: Walker,
+    options: &HashOptions,
+    hash: F,
+) -> Result<(Vec<T>, Vec<EntryError>), Error>
+where
+    T: Send,
+    F: Fn(&Path, &WalkEntry) -> Result<T, Error> + Sync,
+{
     // construct iterator that retrieves system path batches using walker
     let batch_iter = iter::from_fn(move || {
         let mut batch = Vec::with_capacity(PATH_BATCH_SIZE);
         for _ in 0..PATH_BATCH_SIZE {
             match walker.next() {
-                Some(Ok(entry)) => batch.push(entry),
-                Some(Err(e)) => panic!("Critical: Failed to traverse directory: {e}"),
+                Some(entry) => batch.push(entry),
                 None => break,
             }
         }
         if batch.is_empty() { None } else { Some(batch) }
     });
 
-    // run hashing pipeline using parallel batching
-    let mut hashes: Vec<[u8; 32]> = batch_iter
-        .par_bridge()
-        .flat_map_iter(|batch| {
-            batch.into_iter().map(|entry| {
-                hash_path(source, &entry)
-            })
-        })
+    // paths are only needed to drop entries that also failed
+    let with_path = options.on_error != OnError::Fail;
+    // errors that fail hashing stop pipeline, others are kept alongside hashed entries
+    let hash_entry = |entry: Result<WalkEntry, Error>| -> Result<Result<(Option<PathBuf>, T), Error>, Error> {
+        let result = entry.and_then(|entry| {
+            let value = hash(source, &entry)?;
+            Ok((with_path.then(|| relative_path(source, entry.path()).to_path_buf()), value))
+        });
+        match result {
+            Err(e) if !options.on_error.catches(&e) => Err(e),
+            result => Ok(result),
+        }
+    };
+
+    let results: Vec<Result<(Option<PathBuf>, T), Error>> = if options.is_sequential() {
+        batch_iter
+            .flatten()
+            .map(hash_entry)
+            .collect::<Result<_, _>>()?
+    } else {
+        // run hashing pipeline using parallel batching
+        options.install(|| {
+            batch_iter
+                .par_bridge()
+                .flat_map_iter(|batch| batch.into_iter().map(hash_entry))
+                .collect::<Result<_, _>>()
+        })?
+    };
+
+    let mut values = Vec::with_capacity(results.len());
+    let mut errors = Vec::new();
+    for result in results {
+        match result {
+            Ok(value) => values.push(value),
+            Err(e) => errors.push(EntryError::from(&e)),
+        }
+    }
+    if errors.is_empty() {
+        return Ok((values.into_iter().map(|(_, value)| value).collect(), errors));
+    }
+    errors.sort_by(|a, b| a.path.cmp(&b.path));
+    errors.dedup_by(|a, b| a.path == b.path);
+    let failed: HashSet<&Path> = errors.iter().map(|error| error.path.as_path()).collect();
+    let values = values
+        .into_iter()
+        .filter(|(path, _)| !path.as_deref().is_some_and(|path| failed.contains(path)))
+        .map(|(_, value)| value)
         .collect();
+    Ok((values, errors))
+}
 
-    // parallel sort using default rayon MAX_SEQUENTIAL threshold (2k items)
-    hashes.par_sort_unstable();
+/// Source file is hashed as archive (directories are hashed as usual).
+fn is_archive(source: &Path, options: &HashOptions) -> bool {
+    options.archive && source.is_file()
+}
+
+fn hash_with_options(source: &Path, options: &HashOptions) -> Result<Vec<u8>, Error> {
+    options.validate()?;
+    if is_archive(source, options) {
+        let mut hashes: Vec<Digest> = archive_entries(source, options)?
+            .into_iter()
+            .map(|entry| entry.digest)
+            .collect();
+        options.sort_unstable_by(&mut hashes, Ord::cmp);
+        return Ok(get_hashes_root(&hashes, options));
+    }
+    let metadata = MetadataEncoder::new(options);
+    let cache = load_cache(options);
+    let (mut hashes, errors) = hash_entries(source, options, |root, entry| {
+        hash_entry(root, entry, options, &metadata, cache.as_ref())
+    })?;
+    if let Some(cache) = cache {
+        cache.save()?;
+    }
+    hashes.extend(error_entries(&errors, options).map(|entry| entry.digest));
+
+    options.sort_unstable_by(&mut hashes, Ord::cmp);
+
+    Ok(get_hashes_root(&hashes, options))
+}
 
-    get_hashes_root(hashes)
+fn tar_with_options(reader: impl Read, options: &HashOptions) -> Result<Manifest, Error> {
+    options.validate()?;
+    let entries = tar_entries(reader, options)?;
+    let options = HashOptions {
+        cache: None,
+        progress: None,
+        ..options.clone()
+    };
+    Ok(Manifest::new(entries, options))
+}
+
+fn manifest_with_options(source: &Path, options: &HashOptions) -> Result<Manifest, Error> {
+    build_manifest(source, options, true)
+}
+
+fn report_with_options(source: &Path, options: &HashOptions) -> Result<Report, Error> {
+    // reports only count entries and bytes, so content digests are not computed
+    Ok(Report::from_manifest(&build_manifest(source, options, false)?))
+}
+
+/// Manifest of source, with content digests of entries if `with_content` (see [`cached_entry`]).
+fn build_manifest(source: &Path, options: &HashOptions, with_content: bool) -> Result<Manifest, Error> {
+    options.validate()?;
+    let (entries, errors) = if is_archive(source, options) {
+        (archive_entries(source, options)?, Vec::new())
+    } else {
+        let metadata = MetadataEncoder::new(options);
+        let cache = load_cache(options);
+        let (mut entries, errors) = hash_entries(source, options, |root, entry| {
+            cached_entry(root, entry, options, &metadata, cache.as_ref(), with_content)
+        })?;
+        if let Some(cache) = cache {
+            cache.save()?;
+        }
+        entries.extend(error_entries(&errors, options));
+        (entries, errors)
+    };
+    // cache file and progress receiver do not affect hashes, so are not recorded (parallelism is kept for reuse)
+    let options = HashOptions {
+        cache: None,
+        progress: None,
+        ..options.clone()
+    };
+    Ok(Manifest::new(entries, options).with_errors(errors))
 }
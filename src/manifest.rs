@@ -0,0 +1,344 @@
+use std::{
+    fmt,
+    io::{
+        self,
+        prelude::*,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    str::FromStr,
+};
+
+use serde_json::{
+    json,
+    Map,
+    Value,
+};
+
+use super::{
+    digest::{
+        from_hex,
+        to_hex,
+        Digest,
+    },
+    error::EntryError,
+    get_hashes_root,
+    options::HashOptions,
+};
+
+
+/// File system entry type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+    /// Entry that could not be read, recorded in place of entry (see [`OnError::Record`](super::OnError::Record)).
+    Error,
+}
+
+impl EntryKind {
+    /// Name used in manifest output.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EntryKind::File => "file",
+            EntryKind::Dir => "dir",
+            EntryKind::Symlink => "symlink",
+            EntryKind::Error => "error",
+        }
+    }
+
+    /// Type tag prefixed to entry hash input for hash version 2.
+    pub(crate) fn tag(&self) -> u8 {
+        match self {
+            EntryKind::File => b'f',
+            EntryKind::Dir => b'd',
+            EntryKind::Symlink => b'l',
+            EntryKind::Error => b'e',
+        }
+    }
+}
+
+impl fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for EntryKind {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "file" => Ok(EntryKind::File),
+            "dir" => Ok(EntryKind::Dir),
+            "symlink" => Ok(EntryKind::Symlink),
+            "error" => Ok(EntryKind::Error),
+            _ => Err(invalid_data(format!("unknown entry type `{s}`"))),
+        }
+    }
+}
+
+/// Hashed file system entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// Path relative to source root using `/` separators.
+    ///
+    /// Raw `OsStr` bytes on Unix, which need not be valid UTF-8 (see [`Manifest::write_json`] for escaping).
+    pub path: PathBuf,
+    /// Entry type.
+    pub kind: EntryKind,
+    /// File size or symlink target length in bytes (zero for directories and errors).
+    pub size: u64,
+    /// Digest of content only (file bytes, symlink target or error type); matches `b3sum` or `sha256sum` etc. for files.
+    pub content: Digest,
+    /// Digest of path and content that feeds source root.
+    pub digest: Digest,
+}
+
+/// Source root hash with per-entry digests.
+///
+/// Entries are sorted by path. Root is derived from entry digests alone (see [`Manifest::derive_root`]).
+///
+/// Options used for hashing are recorded so that source can be hashed again reproducibly.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Manifest {
+    pub(crate) root: String,
+    pub(crate) options: HashOptions,
+    pub(crate) entries: Vec<Entry>,
+    pub(crate) errors: Vec<EntryError>,
+}
+
+impl Manifest {
+    pub(crate) fn new(mut entries: Vec<Entry>, options: HashOptions) -> Self {
+        options.sort_unstable_by(&mut entries, |a, b| a.path.cmp(&b.path));
+        let root = options.label(&derive_root(&entries, &options));
+        Manifest {
+            root,
+            options,
+            entries,
+            errors: Vec::new(),
+        }
+    }
+
+    pub(crate) fn with_errors(mut self, errors: Vec<EntryError>) -> Self {
+        self.errors = errors;
+        self
+    }
+
+    /// Source root hash, prefixed by hash version and algorithm (see [`HashOptions::hash`]).
+    pub fn root(&self) -> &str {
+        &self.root
+    }
+
+    /// Options used to hash source.
+    pub fn options(&self) -> &HashOptions {
+        &self.options
+    }
+
+    /// Hashed entries sorted by path.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Entries skipped or recorded while hashing, sorted by path (see [`HashOptions::on_error`]).
+    ///
+    /// Errors are not written to JSON, so manifests read from JSON have none; recorded errors remain
+    /// as entries of type [`EntryKind::Error`].
+    pub fn errors(&self) -> &[EntryError] {
+        &self.errors
+    }
+
+    /// Derive source root from entry digests.
+    ///
+    /// Entry digests are sorted bytewise, concatenated and hashed using options algorithm.
+    pub fn derive_root(&self) -> String {
+        self.options.label(&derive_root(&self.entries, &self.options))
+    }
+
+    /// Write manifest as JSON.
+    ///
+    /// Paths are written as UTF-8 with `%` and bytes that are not valid UTF-8 percent-encoded (`%25`, `%FF`),
+    /// so any file name is represented losslessly.
+    pub fn write_json<W: Write>(&self, writer: W) -> io::Result<()> {
+        let entries: Vec<Value> = self.entries
+            .iter()
+            .map(|entry| json!({
+                "path": escape_path(&entry.path),
+                "type": entry.kind.as_str(),
+                "size": entry.size,
+                "content": to_hex(&entry.content),
+                "digest": to_hex(&entry.digest),
+            }))
+            .collect();
+        let manifest = json!({
+            "root": self.root,
+            "options": self.options.to_json(),
+            "entries": entries,
+        });
+        serde_json::to_writer_pretty(writer, &manifest)?;
+        Ok(())
+    }
+
+    /// Write `<content digest>  <path>` line for each file, as `b3sum` or `sha256sum` etc. would.
+    ///
+    /// Paths are relative to source root, so listing can be checked from source root using `b3sum --check`
+    /// (or `sha256sum -c` etc. matching algorithm). Single-file source is listed by file name of source path,
+    /// so can be checked from its parent directory. Paths containing `\` or newlines are escaped and their
+    /// line prefixed by `\`, as those tools do.
+    pub fn write_checksums<W: Write>(&self, mut writer: W, source: &Path) -> io::Result<()> {
+        for entry in self.entries.iter().filter(|entry| entry.kind == EntryKind::File) {
+            let path = if entry.path.as_os_str().is_empty() {
+                source.file_name().unwrap_or(source.as_os_str())
+            } else {
+                entry.path.as_os_str()
+            };
+            let path = path.as_encoded_bytes();
+            let digest = to_hex(&entry.content);
+            if path.iter().any(|byte| matches!(byte, b'\\' | b'\n' | b'\r')) {
+                write!(writer, "\\{digest}  ")?;
+                for byte in path {
+                    match byte {
+                        b'\\' => writer.write_all(b"\\\\")?,
+                        b'\n' => writer.write_all(b"\\n")?,
+                        b'\r' => writer.write_all(b"\\r")?,
+                        _ => writer.write_all(&[*byte])?,
+                    }
+                }
+            } else {
+                write!(writer, "{digest}  ")?;
+                writer.write_all(path)?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+
+    /// Read manifest from JSON.
+    ///
+    /// Fails if JSON is malformed, root does not match entry digests, or manifest is keyed
+    /// (see [`Manifest::read_json_keyed`]).
+    pub fn read_json<R: Read>(reader: R) -> io::Result<Manifest> {
+        read_json(reader, None)
+    }
+
+    /// Read manifest written using [`HashOptions::key`] from JSON.
+    ///
+    /// Key is required to check root against entry digests, and is set on manifest options.
+    /// Manifests that are not keyed are read as by [`Manifest::read_json`].
+    pub fn read_json_keyed<R: Read>(reader: R, key: [u8; 32]) -> io::Result<Manifest> {
+        read_json(reader, Some(key))
+    }
+}
+
+fn read_json<R: Read>(reader: R, key: Option<[u8; 32]>) -> io::Result<Manifest> {
+    let value: Value = serde_json::from_reader(reader)?;
+    let manifest = value
+        .as_object()
+        .ok_or_else(|| invalid_data("manifest must be a JSON object"))?;
+    let root = get_str(manifest, "root")?;
+    let options = match manifest.get("options") {
+        Some(options) => HashOptions::from_json(options, key)?,
+        None => HashOptions::default(),
+    };
+    let entries = manifest
+        .get("entries")
+        .and_then(Value::as_array)
+        .ok_or_else(|| invalid_data("manifest field `entries` must be an array"))?
+        .iter()
+        .map(|value| {
+            let entry = value
+                .as_object()
+                .ok_or_else(|| invalid_data("manifest entry must be a JSON object"))?;
+            Ok(Entry {
+                path: unescape_path(get_str(entry, "path")?)?,
+                kind: get_str(entry, "type")?.parse()?,
+                size: entry
+                    .get("size")
+                    .and_then(Value::as_u64)
+                    .ok_or_else(|| invalid_data("manifest field `size` must be an integer"))?,
+                content: get_digest(entry, "content", &options)?,
+                digest: get_digest(entry, "digest", &options)?,
+            })
+        })
+        .collect::<io::Result<Vec<Entry>>>()?;
+
+    let manifest = Manifest::new(entries, options);
+    if manifest.root != root {
+        return Err(invalid_data("manifest root does not match entry digests"));
+    }
+    Ok(manifest)
+}
+
+fn derive_root(entries: &[Entry], options: &HashOptions) -> Vec<u8> {
+    let mut digests: Vec<Digest> = entries.iter().map(|entry| entry.digest.clone()).collect();
+    digests.sort_unstable();
+    get_hashes_root(&digests, options)
+}
+
+/// Percent-encode `%` and bytes of path that are not valid UTF-8.
+pub(crate) fn escape_path(path: &Path) -> String {
+    let mut escaped = String::new();
+    for chunk in path.as_os_str().as_encoded_bytes().utf8_chunks() {
+        escaped.push_str(&chunk.valid().replace('%', "%25"));
+        for byte in chunk.invalid() {
+            escaped.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    escaped
+}
+
+/// Decode path escaped using [`escape_path`].
+pub(crate) fn unescape_path(escaped: &str) -> io::Result<PathBuf> {
+    let mut bytes = Vec::with_capacity(escaped.len());
+    let mut rest = escaped.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let byte = tail
+                .get(..2)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| invalid_data(format!("manifest path `{escaped}` has invalid escape")))?;
+            bytes.push(byte);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    #[cfg(target_family = "unix")]
+    {
+        use std::{
+            ffi::OsString,
+            os::unix::ffi::OsStringExt,
+        };
+
+        Ok(PathBuf::from(OsString::from_vec(bytes)))
+    }
+    #[cfg(target_family = "windows")]
+    {
+        String::from_utf8(bytes)
+            .map(PathBuf::from)
+            .map_err(|_| invalid_data(format!("manifest path `{escaped}` is not valid UTF-8")))
+    }
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn get_str<'a>(object: &'a Map<String, Value>, field: &str) -> io::Result<&'a str> {
+    object
+        .get(field)
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid_data(format!("manifest field `{field}` must be a string")))
+}
+
+fn get_digest(object: &Map<String, Value>, field: &str, options: &HashOptions) -> io::Result<Digest> {
+    let len = options.algorithm.output_len();
+    from_hex(get_str(object, field)?, len).ok_or_else(|| {
+        invalid_data(format!("manifest field `{field}` is not a valid {len} byte digest"))
+    })
+}
@@ -0,0 +1,333 @@
+use std::{
+    collections::HashMap,
+    fs::Metadata,
+    io,
+    path::Path,
+    str::FromStr,
+    sync::Mutex,
+    time::{
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use super::options::HashOptions;
+
+
+/// File mode bits included in entry hash.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ModeBits {
+    /// Mode is not hashed.
+    #[default]
+    None,
+    /// Whether any executable bit is set (read-only attribute on Windows is not hashed).
+    Exec,
+    /// Permission bits including setuid, setgid and sticky bits (read-only attribute on Windows).
+    Full,
+}
+
+impl ModeBits {
+    /// Name used in options output.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModeBits::None => "none",
+            ModeBits::Exec => "exec",
+            ModeBits::Full => "full",
+        }
+    }
+}
+
+impl FromStr for ModeBits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(ModeBits::None),
+            "exec" => Ok(ModeBits::Exec),
+            "full" => Ok(ModeBits::Full),
+            _ => Err(format!("unknown mode bits `{s}`")),
+        }
+    }
+}
+
+/// Granularity modification times are truncated to before hashing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeGranularity {
+    Seconds,
+    Millis,
+    Micros,
+    Nanos,
+}
+
+impl TimeGranularity {
+    /// Name used in options output.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimeGranularity::Seconds => "s",
+            TimeGranularity::Millis => "ms",
+            TimeGranularity::Micros => "us",
+            TimeGranularity::Nanos => "ns",
+        }
+    }
+
+    fn nanos(&self) -> i128 {
+        match self {
+            TimeGranularity::Seconds => 1_000_000_000,
+            TimeGranularity::Millis => 1_000_000,
+            TimeGranularity::Micros => 1_000,
+            TimeGranularity::Nanos => 1,
+        }
+    }
+}
+
+impl FromStr for TimeGranularity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "s" => Ok(TimeGranularity::Seconds),
+            "ms" => Ok(TimeGranularity::Millis),
+            "us" => Ok(TimeGranularity::Micros),
+            "ns" => Ok(TimeGranularity::Nanos),
+            _ => Err(format!("unknown time granularity `{s}`")),
+        }
+    }
+}
+
+/// Encodes metadata selected by [`HashOptions`] for entry hash input.
+///
+/// Fields are fixed width little-endian integers in order mode, uid and gid, owner and group names, mtime.
+/// Names are length-prefixed (`u64::MAX` length if unknown). Owner names are cached per hashing run.
+/// Extended attributes follow as count and length-prefixed name and value pairs sorted by name.
+pub(crate) struct MetadataEncoder {
+    mode: ModeBits,
+    owner_ids: bool,
+    owner_names: bool,
+    mtime: Option<TimeGranularity>,
+    xattrs: bool,
+    xattr_include: Vec<String>,
+    xattr_exclude: Vec<String>,
+    user_names: Mutex<HashMap<u32, Option<Vec<u8>>>>,
+    group_names: Mutex<HashMap<u32, Option<Vec<u8>>>>,
+}
+
+impl MetadataEncoder {
+    pub(crate) fn new(options: &HashOptions) -> Self {
+        MetadataEncoder {
+            mode: options.mode,
+            owner_ids: options.owner_ids,
+            owner_names: options.owner_names,
+            mtime: options.mtime,
+            xattrs: options.xattrs,
+            xattr_include: options.xattr_include.clone(),
+            xattr_exclude: options.xattr_exclude.clone(),
+            user_names: Mutex::new(HashMap::new()),
+            group_names: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// No metadata is hashed, so metadata need not be read.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.mode == ModeBits::None
+            && !self.owner_ids
+            && !self.owner_names
+            && self.mtime.is_none()
+            && !self.xattrs
+    }
+
+    pub(crate) fn encode(&self, metadata: &Metadata) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match self.mode {
+            ModeBits::None => {}
+            ModeBits::Exec => bytes.push(is_executable(metadata) as u8),
+            ModeBits::Full => bytes.extend_from_slice(&permission_bits(metadata).to_le_bytes()),
+        }
+        let (uid, gid) = owner_ids(metadata);
+        if self.owner_ids {
+            bytes.extend_from_slice(&uid.to_le_bytes());
+            bytes.extend_from_slice(&gid.to_le_bytes());
+        }
+        if self.owner_names {
+            let names = [
+                cached_name(&self.user_names, uid, user_name),
+                cached_name(&self.group_names, gid, group_name),
+            ];
+            for name in names {
+                match name {
+                    Some(name) => {
+                        bytes.extend_from_slice(&(name.len() as u64).to_le_bytes());
+                        bytes.extend_from_slice(&name);
+                    }
+                    None => bytes.extend_from_slice(&u64::MAX.to_le_bytes()),
+                }
+            }
+        }
+        if let Some(granularity) = self.mtime {
+            let mtime = metadata.modified().map(unix_nanos).unwrap_or_default();
+            bytes.extend_from_slice(&mtime.div_euclid(granularity.nanos()).to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Encode extended attributes of entry (of symlink itself unless followed).
+    ///
+    /// File systems without extended attribute support have none.
+    pub(crate) fn encode_xattrs(&self, path: &Path, is_symlink: bool) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        if !self.xattrs {
+            return Ok(bytes);
+        }
+        let mut xattrs = read_xattrs(path, is_symlink)?;
+        xattrs.retain(|(name, _)| self.is_xattr_selected(name));
+        xattrs.sort_unstable();
+        bytes.extend_from_slice(&(xattrs.len() as u64).to_le_bytes());
+        for (name, value) in xattrs {
+            bytes.extend_from_slice(&(name.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(&name);
+            bytes.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(&value);
+        }
+        Ok(bytes)
+    }
+
+    fn is_xattr_selected(&self, name: &[u8]) -> bool {
+        let matches = |namespace: &String| {
+            let namespace = namespace.as_bytes();
+            name.strip_prefix(namespace)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with(b"."))
+        };
+        (self.xattr_include.is_empty() || self.xattr_include.iter().any(matches))
+            && !self.xattr_exclude.iter().any(matches)
+    }
+}
+
+/// Extended attribute names and values, dereferencing path unless symlink is not followed.
+#[cfg(target_family = "unix")]
+fn read_xattrs(path: &Path, is_symlink: bool) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let names = if is_symlink { xattr::list(path) } else { xattr::list_deref(path) };
+    let names = match names {
+        Ok(names) => names,
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut xattrs = Vec::new();
+    for name in names {
+        let value = if is_symlink { xattr::get(path, &name) } else { xattr::get_deref(path, &name) };
+        // attribute removed while reading is skipped
+        if let Some(value) = value? {
+            xattrs.push((name.as_bytes().to_vec(), value));
+        }
+    }
+    Ok(xattrs)
+}
+
+#[cfg(not(target_family = "unix"))]
+fn read_xattrs(_: &Path, _: bool) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    Ok(Vec::new())
+}
+
+fn cached_name(
+    names: &Mutex<HashMap<u32, Option<Vec<u8>>>>,
+    id: u32,
+    lookup: fn(u32) -> Option<Vec<u8>>,
+) -> Option<Vec<u8>> {
+    let mut names = names.lock().unwrap_or_else(|e| e.into_inner());
+    names.entry(id).or_insert_with(|| lookup(id)).clone()
+}
+
+/// Nanoseconds since Unix epoch (negative before epoch).
+fn unix_nanos(time: SystemTime) -> i128 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_nanos() as i128,
+        Err(e) => -(e.duration().as_nanos() as i128),
+    }
+}
+
+#[cfg(target_family = "unix")]
+fn is_executable(metadata: &Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(target_family = "unix"))]
+fn is_executable(_: &Metadata) -> bool {
+    false
+}
+
+#[cfg(target_family = "unix")]
+fn permission_bits(metadata: &Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(target_family = "unix"))]
+fn permission_bits(metadata: &Metadata) -> u32 {
+    metadata.permissions().readonly() as u32
+}
+
+#[cfg(target_family = "unix")]
+fn owner_ids(metadata: &Metadata) -> (u32, u32) {
+    use std::os::unix::fs::MetadataExt;
+
+    (metadata.uid(), metadata.gid())
+}
+
+#[cfg(not(target_family = "unix"))]
+fn owner_ids(_: &Metadata) -> (u32, u32) {
+    (0, 0)
+}
+
+/// Look up name using reentrant passwd or group function, growing buffer as needed.
+#[cfg(target_family = "unix")]
+fn lookup_name<T>(
+    lookup: impl Fn(&mut T, &mut [libc::c_char], &mut *mut T) -> libc::c_int,
+    name: impl Fn(&T) -> *const libc::c_char,
+) -> Option<Vec<u8>> {
+    use std::ffi::CStr;
+
+    let mut buffer: Vec<libc::c_char> = vec![0; 1024];
+    loop {
+        let mut entry: T = unsafe { std::mem::zeroed() };
+        let mut result: *mut T = std::ptr::null_mut();
+        match lookup(&mut entry, &mut buffer, &mut result) {
+            0 if result.is_null() => return None,
+            0 => return Some(unsafe { CStr::from_ptr(name(&entry)) }.to_bytes().to_vec()),
+            libc::ERANGE if buffer.len() < 1024 * 1024 => buffer.resize(buffer.len() * 2, 0),
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(target_family = "unix")]
+fn user_name(uid: u32) -> Option<Vec<u8>> {
+    lookup_name(
+        |entry: &mut libc::passwd, buffer, result| unsafe {
+            libc::getpwuid_r(uid, entry, buffer.as_mut_ptr(), buffer.len(), result)
+        },
+        |entry| entry.pw_name,
+    )
+}
+
+#[cfg(target_family = "unix")]
+fn group_name(gid: u32) -> Option<Vec<u8>> {
+    lookup_name(
+        |entry: &mut libc::group, buffer, result| unsafe {
+            libc::getgrgid_r(gid, entry, buffer.as_mut_ptr(), buffer.len(), result)
+        },
+        |entry| entry.gr_name,
+    )
+}
+
+#[cfg(not(target_family = "unix"))]
+fn user_name(_: u32) -> Option<Vec<u8>> {
+    None
+}
+
+#[cfg(not(target_family = "unix"))]
+fn group_name(_: u32) -> Option<Vec<u8>> {
+    None
+}
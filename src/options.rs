@@ -0,0 +1,973 @@
+use std::{
+    cmp,
+    fmt,
+    io::{
+        self,
+        Read,
+    },
+    ops::ControlFlow,
+    path::{
+        Path,
+        PathBuf,
+    },
+    str::FromStr,
+    sync::{
+        mpsc::{
+            self,
+            Receiver,
+        },
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use constant_time_eq::constant_time_eq;
+use rayon::{
+    prelude::*,
+    ThreadPool,
+};
+use serde_json::{
+    json,
+    Value,
+};
+
+use super::{
+    digest::{
+        to_hex,
+        Algorithm,
+        Digest,
+        Hasher,
+        Key,
+        MAX_OUTPUT_LEN,
+        KEYED_NAME,
+    },
+    error::{
+        Error,
+        OnError,
+    },
+    metadata::{
+        ModeBits,
+        TimeGranularity,
+    },
+    walk::DanglingSymlinks,
+    get_hashes_root,
+    hash_with_options,
+    manifest::{
+        EntryKind,
+        Manifest,
+    },
+    manifest_with_options,
+    progress::{
+        scan,
+        Progress,
+        ProgressSink,
+        ScanTotals,
+    },
+    report::Report,
+    report_with_options,
+    tar_with_options,
+    tree::Tree,
+    watch::{
+        watch,
+        WatchEvent,
+    },
+};
+
+
+/// Entry encoding scheme, identified by root hash prefix.
+///
+/// Version 1 remains the default so existing hashes stay stable.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HashVersion {
+    /// Relative path followed by content (no prefix).
+    ///
+    /// Ambiguous across entries, e.g. file `ab` containing `c` and file `a` containing `bc`.
+    #[default]
+    V1,
+    /// Type tag and length-prefixed relative path followed by content (`paq2:` prefix).
+    V2,
+}
+
+impl HashVersion {
+    /// Version number used in options output.
+    pub fn as_u64(&self) -> u64 {
+        match self {
+            HashVersion::V1 => 1,
+            HashVersion::V2 => 2,
+        }
+    }
+
+    /// Prefix of root hash string.
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            HashVersion::V1 => "",
+            HashVersion::V2 => "paq2:",
+        }
+    }
+
+    /// Detect version from prefix of root hash string (hashes without version prefix are version 1).
+    ///
+    /// ```
+    /// use paq::HashVersion;
+    ///
+    /// assert_eq!(HashVersion::from_hash("paq2:4d1c"), HashVersion::V2);
+    /// assert_eq!(HashVersion::from_hash("4d1c"), HashVersion::V1);
+    /// ```
+    pub fn from_hash(hash: &str) -> Self {
+        if hash.trim_start().starts_with(HashVersion::V2.prefix()) {
+            HashVersion::V2
+        } else {
+            HashVersion::V1
+        }
+    }
+}
+
+impl FromStr for HashVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1" => Ok(HashVersion::V1),
+            "2" => Ok(HashVersion::V2),
+            _ => Err(format!("unknown hash version `{s}`")),
+        }
+    }
+}
+
+/// Threads used to walk, hash and sort entries.
+///
+/// ```
+/// use std::sync::Arc;
+/// use paq::{HashOptions, Parallelism};
+///
+/// let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+/// let source = std::path::PathBuf::from("example");
+/// let pooled = HashOptions::new().parallelism(Parallelism::Pool(Arc::new(pool)));
+/// let sequential = HashOptions::new().parallelism(Parallelism::Sequential);
+///
+/// assert_eq!(pooled.hash(&source).unwrap(), sequential.hash(&source).unwrap());
+/// ```
+#[derive(Clone, Default)]
+pub enum Parallelism {
+    /// Global rayon thread pool, shared with other rayon work of process.
+    #[default]
+    Global,
+    /// Caller-supplied rayon thread pool.
+    Pool(Arc<ThreadPool>),
+    /// Calling thread only, without spawning or using threads.
+    Sequential,
+}
+
+impl fmt::Debug for Parallelism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Parallelism::Global => f.write_str("Global"),
+            Parallelism::Pool(pool) => write!(f, "Pool({} threads)", pool.current_num_threads()),
+            Parallelism::Sequential => f.write_str("Sequential"),
+        }
+    }
+}
+
+/// Pools are compared by identity.
+impl PartialEq for Parallelism {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Parallelism::Global, Parallelism::Global) | (Parallelism::Sequential, Parallelism::Sequential) => true,
+            (Parallelism::Pool(a), Parallelism::Pool(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Parallelism {}
+
+/// Configuration for hashing file system source.
+///
+/// Defaults match [`hash_source`](super::hash_source) with `ignore_hidden` set to `false`.
+///
+/// ```
+/// use paq::HashOptions;
+///
+/// let source = std::path::PathBuf::from("example");
+/// let source_hash = HashOptions::new()
+///     .ignore_hidden(true)
+///     .hash(&source)
+///     .unwrap();
+///
+/// assert_eq!(&source_hash[..], "d7d25c9b2fdb7391e650085a985ad0d892c7f0dd5edd32c7ccdb4b0d1c34c430");
+/// ```
+///
+/// Options are equal if they hash alike; cache file, progress receiver and parallelism are not compared.
+#[derive(Clone, Debug, Default)]
+pub struct HashOptions {
+    pub(crate) ignore_hidden: bool,
+    pub(crate) ignore_files: bool,
+    pub(crate) global_ignore: bool,
+    pub(crate) include: Vec<String>,
+    pub(crate) exclude: Vec<String>,
+    pub(crate) follow_symlinks: bool,
+    pub(crate) dangling_symlinks: DanglingSymlinks,
+    pub(crate) hash_version: HashVersion,
+    pub(crate) algorithm: Algorithm,
+    pub(crate) key: Option<Key>,
+    pub(crate) length: Option<usize>,
+    pub(crate) mode: ModeBits,
+    pub(crate) owner_ids: bool,
+    pub(crate) owner_names: bool,
+    pub(crate) mtime: Option<TimeGranularity>,
+    pub(crate) xattrs: bool,
+    pub(crate) xattr_include: Vec<String>,
+    pub(crate) xattr_exclude: Vec<String>,
+    pub(crate) cache: Option<PathBuf>,
+    pub(crate) archive: bool,
+    pub(crate) on_error: OnError,
+    pub(crate) progress: Option<ProgressSink>,
+    pub(crate) parallelism: Parallelism,
+}
+
+impl PartialEq for HashOptions {
+    fn eq(&self, other: &Self) -> bool {
+        // destructured so that added options are compared unless listed as not affecting hashes
+        let HashOptions {
+            ignore_hidden,
+            ignore_files,
+            global_ignore,
+            include,
+            exclude,
+            follow_symlinks,
+            dangling_symlinks,
+            hash_version,
+            algorithm,
+            key,
+            length,
+            mode,
+            owner_ids,
+            owner_names,
+            mtime,
+            xattrs,
+            xattr_include,
+            xattr_exclude,
+            cache: _,
+            archive,
+            on_error,
+            progress: _,
+            parallelism: _,
+        } = self;
+        *ignore_hidden == other.ignore_hidden
+            && *ignore_files == other.ignore_files
+            && *global_ignore == other.global_ignore
+            && *include == other.include
+            && *exclude == other.exclude
+            && *follow_symlinks == other.follow_symlinks
+            && *dangling_symlinks == other.dangling_symlinks
+            && *hash_version == other.hash_version
+            && *algorithm == other.algorithm
+            && *key == other.key
+            && *length == other.length
+            && *mode == other.mode
+            && *owner_ids == other.owner_ids
+            && *owner_names == other.owner_names
+            && *mtime == other.mtime
+            && *xattrs == other.xattrs
+            && *xattr_include == other.xattr_include
+            && *xattr_exclude == other.xattr_exclude
+            && *archive == other.archive
+            && *on_error == other.on_error
+    }
+}
+
+impl Eq for HashOptions {}
+
+impl HashOptions {
+    /// Create options using defaults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ignore files or directories starting with dot or full stop.
+    pub fn ignore_hidden(mut self, ignore_hidden: bool) -> Self {
+        self.ignore_hidden = ignore_hidden;
+        self
+    }
+
+    /// Apply gitignore-style rules from ignore files during traversal.
+    ///
+    /// Reads [`IGNORE_FILE_NAMES`](super::IGNORE_FILE_NAMES) in every directory (later files and
+    /// deeper directories take precedence). Rules apply regardless of whether source is a git repository.
+    pub fn ignore_files(mut self, ignore_files: bool) -> Self {
+        self.ignore_files = ignore_files;
+        self
+    }
+
+    /// Also apply global git excludes file (`core.excludesFile`) of hashing user, when applying ignore files.
+    ///
+    /// Rules come from configuration of hashing system rather than source, so hashes may differ between
+    /// systems although this option is recorded in manifests.
+    pub fn global_ignore(mut self, global_ignore: bool) -> Self {
+        self.global_ignore = global_ignore;
+        self
+    }
+
+    /// Only hash files or symlinks matching any of glob patterns.
+    ///
+    /// Patterns match path relative to source root; patterns without `/` match file name at any depth.
+    /// Directories are always traversed. Appends to previously added patterns.
+    pub fn include<I, S>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.include.extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    /// Skip files, symlinks or directories matching any of glob patterns.
+    ///
+    /// Patterns match as in [`HashOptions::include`]; excluded directories are not traversed.
+    /// Appends to previously added patterns.
+    pub fn exclude<I, S>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.exclude.extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    /// Follow symlinks, hashing target contents instead of target path.
+    ///
+    /// Fails with [`Error::Loop`] if a directory is reached again within itself.
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// Handling of symlinks with missing target when following symlinks.
+    pub fn dangling_symlinks(mut self, dangling_symlinks: DanglingSymlinks) -> Self {
+        self.dangling_symlinks = dangling_symlinks;
+        self
+    }
+
+    /// Entry encoding scheme; root hash is prefixed for versions other than 1.
+    pub fn hash_version(mut self, hash_version: HashVersion) -> Self {
+        self.hash_version = hash_version;
+        self
+    }
+
+    /// Hashing algorithm for entry digests and source root.
+    ///
+    /// Root hash is prefixed by algorithm name for algorithms other than BLAKE3, e.g. `sha256:`.
+    /// Library users can plug in other digests using [`Algorithm::custom`].
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Hash using keyed BLAKE3 (a MAC), so hashes cannot be recomputed without key.
+    ///
+    /// Applies to entry digests, content digests and source root. Root hash is prefixed by
+    /// `blake3-keyed:`. Key is never written to output; hashing fails with [`Error::UnsupportedKey`]
+    /// unless algorithm is BLAKE3.
+    pub fn key(mut self, key: [u8; 32]) -> Self {
+        self.key = Some(Key(key));
+        self
+    }
+
+    /// Root hash length in bytes, extended using BLAKE3 extendable output (XOF).
+    ///
+    /// Entry digests keep algorithm length. Defaults to algorithm length; hashing fails with
+    /// [`Error::UnsupportedLength`] if length is zero or above [`MAX_OUTPUT_LEN`], or algorithm is not
+    /// BLAKE3 with other length.
+    pub fn length(mut self, length: usize) -> Self {
+        self.length = Some(length);
+        self
+    }
+
+    /// Include file mode bits in entry hashes.
+    ///
+    /// Metadata of symlinks themselves is hashed unless symlinks are followed.
+    pub fn mode(mut self, mode: ModeBits) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Include numeric owner user and group ids in entry hashes (zero on Windows).
+    pub fn owner_ids(mut self, owner_ids: bool) -> Self {
+        self.owner_ids = owner_ids;
+        self
+    }
+
+    /// Include owner user and group names in entry hashes (unknown on Windows).
+    ///
+    /// Names are resolved on the hashing system, so ids without names hash alike.
+    pub fn owner_names(mut self, owner_names: bool) -> Self {
+        self.owner_names = owner_names;
+        self
+    }
+
+    /// Include modification times truncated to granularity in entry hashes.
+    pub fn mtime(mut self, granularity: Option<TimeGranularity>) -> Self {
+        self.mtime = granularity;
+        self
+    }
+
+    /// Include extended attribute names and values in entry hashes (none on Windows).
+    ///
+    /// Covers file capabilities (`security.capability`), POSIX ACLs (`system.posix_acl_access`)
+    /// and SELinux labels (`security.selinux`). Attributes listed depend on privileges, e.g. `trusted.*` requires root.
+    pub fn xattrs(mut self, xattrs: bool) -> Self {
+        self.xattrs = xattrs;
+        self
+    }
+
+    /// Only hash extended attributes within any of namespaces (e.g. `user` or `security.capability`).
+    ///
+    /// Appends to previously added namespaces.
+    pub fn xattr_include<I, S>(mut self, namespaces: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.xattr_include.extend(namespaces.into_iter().map(Into::into));
+        self
+    }
+
+    /// Skip extended attributes within any of namespaces, taking precedence over included namespaces.
+    ///
+    /// Appends to previously added namespaces.
+    pub fn xattr_exclude<I, S>(mut self, namespaces: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.xattr_exclude.extend(namespaces.into_iter().map(Into::into));
+        self
+    }
+
+    /// Hash source file as tar (plain, gzip, zstd or xz compressed) or zip archive, as if extracted.
+    ///
+    /// Format is detected from file contents. Directory sources are hashed as usual, so recorded option
+    /// allows verifying extracted directory. Ignore files, following symlinks and metadata are not supported.
+    pub fn archive(mut self, archive: bool) -> Self {
+        self.archive = archive;
+        self
+    }
+
+    /// Handling of entries that cannot be read, see [`OnError`].
+    ///
+    /// Skipped and recorded entries are listed by [`Manifest::errors`]; use [`HashOptions::manifest`] to
+    /// report them, as [`HashOptions::hash`] only returns root.
+    ///
+    /// ```
+    /// use paq::{HashOptions, OnError};
+    ///
+    /// let source = std::path::PathBuf::from("example");
+    /// let manifest = HashOptions::new()
+    ///     .on_error(OnError::Record)
+    ///     .manifest(&source)
+    ///     .unwrap();
+    ///
+    /// // readable source hashes as without errors
+    /// assert!(manifest.errors().is_empty());
+    /// assert_eq!(manifest.root(), HashOptions::new().hash(&source).unwrap());
+    /// ```
+    pub fn on_error(mut self, on_error: OnError) -> Self {
+        self.on_error = on_error;
+        self
+    }
+
+    /// Reuse digests of files unchanged since previous run, recorded in cache file at path.
+    ///
+    /// Files are unchanged if device, inode, size, modification and change times match.
+    /// Files changed within a second of hashing are hashed again next run. Cache starts empty if options
+    /// affecting entry digests change. Not used if owner names are hashed, as names can change without files.
+    /// Cache file should be outside source, and is not recorded by manifests. Each run replaces cache file
+    /// with entries of its source, so cache file should not be shared by other sources or concurrent runs.
+    pub fn cache(mut self, path: Option<PathBuf>) -> Self {
+        self.cache = path;
+        self
+    }
+
+    /// Report each hashed entry to progress receiver, see [`Progress`].
+    ///
+    /// Progress receiver does not affect hashes, so is not recorded in manifests.
+    pub fn progress(mut self, progress: Option<Arc<dyn Progress>>) -> Self {
+        self.progress = progress.map(ProgressSink);
+        self
+    }
+
+    /// Threads used for hashing, see [`Parallelism`].
+    ///
+    /// Parallelism does not affect hashes, so is not recorded in manifests.
+    pub fn parallelism(mut self, parallelism: Parallelism) -> Self {
+        self.parallelism = parallelism;
+        self
+    }
+
+    /// Hash file system source using options.
+    ///
+    /// Source **must** be a path to a file or directory.
+    ///
+    /// ```
+    /// use paq::{HashOptions, HashVersion};
+    ///
+    /// let source = std::path::PathBuf::from("example");
+    /// let source_hash = HashOptions::new()
+    ///     .hash_version(HashVersion::V2)
+    ///     .hash(&source)
+    ///     .unwrap();
+    ///
+    /// assert!(source_hash.starts_with("paq2:"));
+    /// ```
+    pub fn hash(&self, source: &Path) -> Result<String, Error> {
+        let root = self.hash_bytes(source)?;
+        Ok(self.label(&root))
+    }
+
+    /// Hash file system source using options, returning root hash bytes without prefix.
+    ///
+    /// Source **must** be a path to a file or directory.
+    ///
+    /// ```
+    /// use paq::HashOptions;
+    ///
+    /// let source = std::path::PathBuf::from("example");
+    /// let root = HashOptions::new()
+    ///     .length(64)
+    ///     .hash_bytes(&source)
+    ///     .unwrap();
+    ///
+    /// assert_eq!(root.len(), 64);
+    /// ```
+    pub fn hash_bytes(&self, source: &Path) -> Result<Vec<u8>, Error> {
+        hash_with_options(source, self)
+    }
+
+    /// Hash file system source using options, returning root hash with per-entry digests.
+    ///
+    /// Source **must** be a path to a file or directory.
+    pub fn manifest(&self, source: &Path) -> Result<Manifest, Error> {
+        manifest_with_options(source, self)
+    }
+
+    /// Hash file system source using options, returning summary of hashed entries (see [`Report`]).
+    ///
+    /// Unlike [`HashOptions::manifest`], content digests are not computed, so files are read once.
+    ///
+    /// ```
+    /// use paq::HashOptions;
+    ///
+    /// let source = std::path::PathBuf::from("example");
+    /// let options = HashOptions::new().ignore_hidden(true);
+    /// let report = options.report(&source).unwrap();
+    ///
+    /// assert_eq!(report.root(), options.hash(&source).unwrap());
+    /// assert_eq!(report.entry_count(), options.manifest(&source).unwrap().entries().len() as u64);
+    /// ```
+    pub fn report(&self, source: &Path) -> Result<Report, Error> {
+        report_with_options(source, self)
+    }
+
+    /// Hash tar stream (plain, gzip, zstd or xz compressed) using options, as if extracted to directory.
+    ///
+    /// Root hash equals hash of extracted directory, see [`HashOptions::archive`].
+    ///
+    /// ```
+    /// use paq::HashOptions;
+    ///
+    /// let mut builder = tar::Builder::new(Vec::new());
+    /// builder.follow_symlinks(false);
+    /// builder.append_dir_all(".", "example").unwrap();
+    /// let tar = builder.into_inner().unwrap();
+    ///
+    /// let options = HashOptions::new().ignore_hidden(true);
+    /// let source_hash = options.hash_tar(tar.as_slice()).unwrap();
+    ///
+    /// assert_eq!(source_hash, options.hash(std::path::Path::new("example")).unwrap());
+    /// ```
+    pub fn hash_tar<R: Read>(&self, reader: R) -> Result<String, Error> {
+        Ok(tar_with_options(reader, self)?.root)
+    }
+
+    /// Hash tar stream using options, returning root hash with per-entry digests.
+    pub fn manifest_tar<R: Read>(&self, reader: R) -> Result<Manifest, Error> {
+        tar_with_options(reader, self)
+    }
+
+    /// Hash file system source using options, returning Merkle tree with per-directory digests.
+    ///
+    /// Flat root hash is reported alongside Merkle root, see [`Tree`].
+    pub fn tree(&self, source: &Path) -> Result<Tree, Error> {
+        let manifest = manifest_with_options(source, self)?;
+        Ok(Tree::from_manifest(&manifest))
+    }
+
+    /// Watch file system source, calling handler with root hash initially and after changes settle.
+    ///
+    /// Changes are collected until none occur for debounce duration. Only changed paths and their descendants
+    /// are walked and hashed again (whole source if following symlinks or notifications were lost).
+    /// Errors while hashing or watching are passed to handler; watching stops once handler
+    /// returns [`ControlFlow::Break`].
+    ///
+    /// ```no_run
+    /// use std::{ops::ControlFlow, time::Duration};
+    /// use paq::HashOptions;
+    ///
+    /// let source = std::path::PathBuf::from("/path/to/source");
+    /// HashOptions::new()
+    ///     .watch(&source, Duration::from_millis(200), |event| {
+    ///         match event {
+    ///             Ok(event) => println!("{}", event.root()),
+    ///             Err(e) => eprintln!("{e}"),
+    ///         }
+    ///         ControlFlow::Continue(())
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn watch<F>(&self, source: &Path, debounce: Duration, handler: F) -> Result<(), Error>
+    where
+        F: FnMut(Result<WatchEvent, Error>) -> ControlFlow<()>,
+    {
+        watch(source, self, debounce, handler)
+    }
+
+    /// Watch file system source on background thread, receiving events of [`HashOptions::watch`].
+    ///
+    /// Watching stops once receiver is dropped and next event is sent.
+    pub fn watch_channel(&self, source: &Path, debounce: Duration) -> Receiver<Result<WatchEvent, Error>> {
+        let (sender, receiver) = mpsc::channel();
+        let options = self.clone();
+        let source = source.to_path_buf();
+        thread::spawn(move || {
+            let result = watch(&source, &options, debounce, |event| match sender.send(event) {
+                Ok(()) => ControlFlow::Continue(()),
+                Err(_) => ControlFlow::Break(()),
+            });
+            // failure to start watching is sent as last event
+            if let Err(e) = result {
+                let _ = sender.send(Err(e));
+            }
+        });
+        receiver
+    }
+
+    /// Count files and bytes of file system source using options, without reading file content.
+    ///
+    /// Walk is sequential and only reads metadata, so is fast relative to hashing, e.g. to estimate time
+    /// remaining from [`Progress`]. Archives are counted as single file.
+    pub fn scan(&self, source: &Path) -> Result<ScanTotals, Error> {
+        scan(source, self)
+    }
+
+    /// Verify file system source matches expected hash using options.
+    ///
+    /// Hash version, algorithm and length are detected from expected hash, so hashes of any version,
+    /// algorithm or length can be verified. Keyed hashes are reported as mismatch unless key is set.
+    /// Comparison runs in constant time. Malformed expected hash is reported as mismatch.
+    pub fn verify(&self, source: &Path, expected: &str) -> Result<bool, Error> {
+        let hash_version = HashVersion::from_hash(expected);
+        let label = &expected.trim()[hash_version.prefix().len()..];
+        let (mut algorithm, keyed) = Algorithm::from_label(label);
+        // custom algorithm is kept if expected hash is named by it
+        if matches!(self.algorithm, Algorithm::Custom(_)) && label.starts_with(&self.algorithm.prefix()) {
+            algorithm = self.algorithm.clone();
+        }
+        let hex = label.rsplit(':').next().unwrap_or_default();
+        // hashes longer than maximum length cannot match (and are not hashed to find out)
+        if keyed != self.key.is_some()
+            || hex.is_empty()
+            || !hex.len().is_multiple_of(2)
+            || hex.len() / 2 > MAX_OUTPUT_LEN
+        {
+            return Ok(false);
+        }
+        let is_blake3 = algorithm == Algorithm::Blake3;
+        let mut options = self
+            .clone()
+            .hash_version(hash_version)
+            .algorithm(algorithm);
+        if is_blake3 {
+            options = options.length(hex.len() / 2);
+        }
+        let hash = options.hash(source)?;
+        Ok(constant_time_eq(hash.as_bytes(), expected.trim().as_bytes()))
+    }
+
+    /// Combine source hashes labeled by source path into single root, e.g. for cache key of several sources.
+    ///
+    /// Each label is bound to its hash, so swapping hashes between sources changes root, while order of
+    /// sources does not. Root is prefixed by hash version and algorithm as [`HashOptions::hash`].
+    ///
+    /// ```
+    /// use paq::HashOptions;
+    ///
+    /// let options = HashOptions::new();
+    /// let alpha = options.hash(std::path::Path::new("example/alpha.txt")).unwrap();
+    /// let charlie = options.hash(std::path::Path::new("example/charlie")).unwrap();
+    ///
+    /// assert_eq!(
+    ///     options.combine([("alpha.txt", &alpha), ("charlie", &charlie)]),
+    ///     options.combine([("charlie", &charlie), ("alpha.txt", &alpha)])
+    /// );
+    /// ```
+    pub fn combine<I, L, H>(&self, hashes: I) -> String
+    where
+        I: IntoIterator<Item = (L, H)>,
+        L: AsRef<Path>,
+        H: AsRef<str>,
+    {
+        let mut digests: Vec<Digest> = hashes
+            .into_iter()
+            .map(|(label, hash)| {
+                let label = label.as_ref().as_os_str().as_encoded_bytes();
+                let hash = hash.as_ref().as_bytes();
+                let mut hasher = self.hasher();
+                hasher.update(&(label.len() as u64).to_le_bytes());
+                hasher.update(label);
+                hasher.update(&(hash.len() as u64).to_le_bytes());
+                hasher.update(hash);
+                hasher.finalize()
+            })
+            .collect();
+        digests.sort_unstable();
+        self.label(&get_hashes_root(&digests, self))
+    }
+
+    /// Digests hashed using options can be compared to digests hashed using other options.
+    ///
+    /// Hash version, algorithm and key must match; other options only select which entries and metadata
+    /// are hashed, so their differences are reported as changes by [`Diff`](super::Diff).
+    ///
+    /// ```
+    /// use paq::{Algorithm, HashOptions};
+    ///
+    /// assert!(HashOptions::new().is_comparable(&HashOptions::new().ignore_hidden(true)));
+    /// assert!(!HashOptions::new().is_comparable(&HashOptions::new().algorithm(Algorithm::Sha256)));
+    /// ```
+    pub fn is_comparable(&self, other: &HashOptions) -> bool {
+        self.hash_version == other.hash_version && self.algorithm == other.algorithm && self.key == other.key
+    }
+
+    /// Report hashed entry to progress receiver, if set.
+    pub(crate) fn entry_hashed(&self, kind: EntryKind, bytes: u64) {
+        if let Some(progress) = &self.progress {
+            progress.0.entry_hashed(kind, bytes);
+        }
+    }
+
+    /// Run operation on thread pool of options, if set, so that parallel iterators within use it.
+    pub(crate) fn install<R, OP>(&self, op: OP) -> R
+    where
+        R: Send,
+        OP: FnOnce() -> R + Send,
+    {
+        match &self.parallelism {
+            Parallelism::Pool(pool) => pool.install(op),
+            Parallelism::Global | Parallelism::Sequential => op(),
+        }
+    }
+
+    pub(crate) fn is_sequential(&self) -> bool {
+        self.parallelism == Parallelism::Sequential
+    }
+
+    /// Sort using threads of options (parallel sort uses rayon MAX_SEQUENTIAL threshold of 2k items).
+    pub(crate) fn sort_unstable_by<T, F>(&self, items: &mut [T], compare: F)
+    where
+        T: Send,
+        F: Fn(&T, &T) -> cmp::Ordering + Send + Sync,
+    {
+        if self.is_sequential() {
+            items.sort_unstable_by(compare);
+        } else {
+            self.install(|| items.par_sort_unstable_by(compare));
+        }
+    }
+
+    /// Hasher of algorithm, keyed if key is set.
+    pub(crate) fn hasher(&self) -> Hasher {
+        Hasher::new(&self.algorithm, self.key.as_ref())
+    }
+
+    /// Fail if options cannot be combined.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if !self.algorithm.is_valid() {
+            return Err(Error::UnsupportedAlgorithm {
+                name: self.algorithm.as_str().to_owned(),
+            });
+        }
+        if self.key.is_some() && self.algorithm != Algorithm::Blake3 {
+            return Err(Error::UnsupportedKey {
+                algorithm: self.algorithm.clone(),
+            });
+        }
+        let length = self.output_len();
+        if length == 0
+            || length > MAX_OUTPUT_LEN
+            || (self.algorithm != Algorithm::Blake3 && length != self.algorithm.output_len())
+        {
+            return Err(Error::UnsupportedLength {
+                algorithm: self.algorithm.clone(),
+                length,
+            });
+        }
+        Ok(())
+    }
+
+    /// Root hash length in bytes.
+    pub(crate) fn output_len(&self) -> usize {
+        self.length.unwrap_or_else(|| self.algorithm.output_len())
+    }
+
+    /// Root hash string prefixed by hash version and algorithm.
+    pub(crate) fn label(&self, root: &[u8]) -> String {
+        let algorithm = match self.key {
+            Some(_) => format!("{KEYED_NAME}:"),
+            None => self.algorithm.prefix(),
+        };
+        format!("{}{algorithm}{}", self.hash_version.prefix(), to_hex(root))
+    }
+
+    /// Options affecting hash output, recorded with manifests for reproducibility.
+    ///
+    /// Key is never recorded, only whether hashes are keyed.
+    pub(crate) fn to_json(&self) -> Value {
+        json!({
+            "ignore_hidden": self.ignore_hidden,
+            "ignore_files": self.ignore_files,
+            "global_ignore": self.global_ignore,
+            "include": self.include,
+            "exclude": self.exclude,
+            "follow_symlinks": self.follow_symlinks,
+            "dangling_symlinks": self.dangling_symlinks.as_str(),
+            "archive": self.archive,
+            "on_error": self.on_error.as_str(),
+            "hash_version": self.hash_version.as_u64(),
+            "algorithm": self.algorithm.as_str(),
+            "keyed": self.key.is_some(),
+            "length": self.output_len(),
+            "mode": self.mode.as_str(),
+            "owner_ids": self.owner_ids,
+            "owner_names": self.owner_names,
+            "mtime": self.mtime.map(|granularity| granularity.as_str()),
+            "xattrs": self.xattrs,
+            "xattr_include": self.xattr_include,
+            "xattr_exclude": self.xattr_exclude,
+        })
+    }
+
+    /// Options recorded by [`HashOptions::to_json`]; missing options use defaults.
+    ///
+    /// Key must be given if recorded options are keyed.
+    pub(crate) fn from_json(value: &Value, key: Option<[u8; 32]>) -> io::Result<Self> {
+        let get_bool = |field: &str| -> io::Result<bool> {
+            match value.get(field) {
+                None => Ok(false),
+                Some(Value::Bool(b)) => Ok(*b),
+                Some(_) => Err(invalid_option(field, "a boolean")),
+            }
+        };
+        let get_strings = |field: &str| -> io::Result<Vec<String>> {
+            match value.get(field) {
+                None => Ok(Vec::new()),
+                Some(Value::Array(values)) => values
+                    .iter()
+                    .map(|value| value.as_str().map(str::to_owned))
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| invalid_option(field, "an array of strings")),
+                Some(_) => Err(invalid_option(field, "an array of strings")),
+            }
+        };
+        let mut options = HashOptions {
+            ignore_hidden: get_bool("ignore_hidden")?,
+            ignore_files: get_bool("ignore_files")?,
+            global_ignore: get_bool("global_ignore")?,
+            include: get_strings("include")?,
+            exclude: get_strings("exclude")?,
+            follow_symlinks: get_bool("follow_symlinks")?,
+            dangling_symlinks: match value.get("dangling_symlinks") {
+                None => DanglingSymlinks::default(),
+                Some(value) => value
+                    .as_str()
+                    .and_then(|s| s.parse().ok())
+                    .ok_or_else(|| invalid_option("dangling_symlinks", "one of error, skip or hash"))?,
+            },
+            hash_version: match value.get("hash_version") {
+                None => HashVersion::default(),
+                Some(value) => value
+                    .as_u64()
+                    .and_then(|version| version.to_string().parse().ok())
+                    .ok_or_else(|| invalid_option("hash_version", "one of 1 or 2"))?,
+            },
+            key: match (get_bool("keyed")?, key) {
+                (true, Some(key)) => Some(Key(key)),
+                (true, None) => return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "options are keyed, key is required",
+                )),
+                (false, _) => None,
+            },
+            algorithm: match value.get("algorithm") {
+                None => Algorithm::default(),
+                Some(value) => value
+                    .as_str()
+                    .and_then(|s| s.parse().ok())
+                    .ok_or_else(|| invalid_option("algorithm", "one of blake3, sha256, sha512 or xxh3"))?,
+            },
+            length: match value.get("length") {
+                None => None,
+                Some(value) => Some(
+                    value
+                        .as_u64()
+                        .and_then(|length| usize::try_from(length).ok())
+                        .ok_or_else(|| invalid_option("length", "an integer"))?,
+                ),
+            },
+            mode: match value.get("mode") {
+                None => ModeBits::default(),
+                Some(value) => value
+                    .as_str()
+                    .and_then(|s| s.parse().ok())
+                    .ok_or_else(|| invalid_option("mode", "one of none, exec or full"))?,
+            },
+            owner_ids: get_bool("owner_ids")?,
+            owner_names: get_bool("owner_names")?,
+            mtime: match value.get("mtime") {
+                None | Some(Value::Null) => None,
+                Some(value) => Some(
+                    value
+                        .as_str()
+                        .and_then(|s| s.parse().ok())
+                        .ok_or_else(|| invalid_option("mtime", "one of s, ms, us, ns or null"))?,
+                ),
+            },
+            xattrs: get_bool("xattrs")?,
+            xattr_include: get_strings("xattr_include")?,
+            xattr_exclude: get_strings("xattr_exclude")?,
+            cache: None,
+            archive: get_bool("archive")?,
+            on_error: match value.get("on_error") {
+                None => OnError::default(),
+                Some(value) => value
+                    .as_str()
+                    .and_then(|s| s.parse().ok())
+                    .ok_or_else(|| invalid_option("on_error", "one of fail, skip or record"))?,
+            },
+            progress: None,
+            parallelism: Parallelism::default(),
+        };
+        // default length is not distinguished from unset length
+        if options.length == Some(options.algorithm.output_len()) {
+            options.length = None;
+        }
+        Ok(options)
+    }
+}
+
+fn invalid_option(field: &str, expected: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("option `{field}` must be {expected}"),
+    )
+}
@@ -0,0 +1,110 @@
+use std::{
+    fmt,
+    path::Path,
+    sync::Arc,
+};
+
+use super::{
+    error::Error,
+    manifest::EntryKind,
+    options::HashOptions,
+    walk::{
+        relative_path,
+        Walker,
+    },
+};
+
+
+/// Receiver of hashing progress set by [`HashOptions::progress`].
+///
+/// Called once per hashed entry from worker threads, concurrently and in no particular order, so
+/// implementations should avoid locking (e.g. count using relaxed atomics). Closures taking entry type
+/// and bytes implement this trait.
+///
+/// ```
+/// use std::sync::{
+///     atomic::{AtomicU64, Ordering},
+///     Arc,
+/// };
+/// use paq::HashOptions;
+///
+/// let bytes = Arc::new(AtomicU64::new(0));
+/// let counter = bytes.clone();
+/// let options = HashOptions::new()
+///     .ignore_hidden(true)
+///     .progress(Some(Arc::new(move |_kind, len| {
+///         counter.fetch_add(len, Ordering::Relaxed);
+///     })));
+/// options.hash(std::path::Path::new("example")).unwrap();
+///
+/// assert_eq!(bytes.load(Ordering::Relaxed), options.scan(std::path::Path::new("example")).unwrap().bytes);
+/// ```
+pub trait Progress: Send + Sync {
+    /// Entry hashed, with content bytes processed (file size or symlink target length, zero for directories).
+    fn entry_hashed(&self, kind: EntryKind, bytes: u64);
+}
+
+impl<F> Progress for F
+where
+    F: Fn(EntryKind, u64) + Send + Sync,
+{
+    fn entry_hashed(&self, kind: EntryKind, bytes: u64) {
+        self(kind, bytes)
+    }
+}
+
+/// Files and bytes of source counted by [`HashOptions::scan`] without reading content.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ScanTotals {
+    /// Number of files (symlinks and directories are not counted).
+    pub files: u64,
+    /// Total file sizes and symlink target lengths in bytes.
+    pub bytes: u64,
+}
+
+/// Progress receiver held by options.
+#[derive(Clone)]
+pub(crate) struct ProgressSink(pub(crate) Arc<dyn Progress>);
+
+impl fmt::Debug for ProgressSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressSink(..)")
+    }
+}
+
+/// Walk source using options, summing sizes from metadata.
+///
+/// Entries that options skip or record on error are not counted.
+pub(crate) fn scan(source: &Path, options: &HashOptions) -> Result<ScanTotals, Error> {
+    let mut totals = ScanTotals::default();
+    for entry in Walker::new(source, options)? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) if options.on_error.catches(&e) => continue,
+            Err(e) => return Err(e),
+        };
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            continue;
+        }
+        // symlink metadata length is target length
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                let e = Error::Metadata {
+                    path: relative_path(source, entry.path()).to_path_buf(),
+                    source: e,
+                };
+                if options.on_error.catches(&e) {
+                    continue;
+                }
+                return Err(e);
+            }
+        };
+        if file_type.is_file() {
+            totals.files += 1;
+        }
+        totals.bytes += metadata.len();
+    }
+    Ok(totals)
+}
@@ -0,0 +1,161 @@
+use std::io::{
+    self,
+    prelude::*,
+};
+
+use serde_json::{
+    json,
+    Value,
+};
+
+use super::{
+    error::EntryError,
+    manifest::Manifest,
+    options::HashOptions,
+};
+
+
+/// Version of report format, incremented when fields change meaning.
+pub const REPORT_VERSION: u64 = 1;
+
+/// Summary of hashing run, written to `.paq` files so they describe how root was derived.
+///
+/// ```
+/// use paq::{HashOptions, Report};
+///
+/// let source = std::path::PathBuf::from("example");
+/// let manifest = HashOptions::new()
+///     .ignore_hidden(true)
+///     .manifest(&source)
+///     .unwrap();
+/// let report = Report::from_manifest(&manifest);
+///
+/// assert_eq!(report.root(), "d7d25c9b2fdb7391e650085a985ad0d892c7f0dd5edd32c7ccdb4b0d1c34c430");
+/// assert_eq!(report.entry_count(), manifest.entries().len() as u64);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    root: String,
+    options: HashOptions,
+    entry_count: u64,
+    total_bytes: u64,
+    tool_version: String,
+    errors: Vec<EntryError>,
+}
+
+impl Report {
+    /// Summarize manifest, recording version of this crate.
+    pub fn from_manifest(manifest: &Manifest) -> Self {
+        Report {
+            root: manifest.root().to_owned(),
+            options: manifest.options().clone(),
+            entry_count: manifest.entries().len() as u64,
+            total_bytes: manifest.entries().iter().map(|entry| entry.size).sum(),
+            tool_version: env!("CARGO_PKG_VERSION").to_owned(),
+            errors: manifest.errors().to_vec(),
+        }
+    }
+
+    /// Source root hash, see [`HashOptions::hash`].
+    pub fn root(&self) -> &str {
+        &self.root
+    }
+
+    /// Options used to hash source.
+    pub fn options(&self) -> &HashOptions {
+        &self.options
+    }
+
+    /// Number of hashed entries, including directories.
+    pub fn entry_count(&self) -> u64 {
+        self.entry_count
+    }
+
+    /// Total size of hashed entries in bytes (file sizes and symlink target lengths).
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes
+    }
+
+    /// Version of `paq` that wrote report.
+    pub fn tool_version(&self) -> &str {
+        &self.tool_version
+    }
+
+    /// Entries skipped or recorded while hashing, see [`Manifest::errors`].
+    ///
+    /// Errors are not written to JSON, so reports read from JSON have none.
+    pub fn errors(&self) -> &[EntryError] {
+        &self.errors
+    }
+
+    /// Write report as JSON.
+    ///
+    /// Algorithm and hash version are repeated beside recorded options for readers of root alone.
+    pub fn write_json<W: Write>(&self, writer: W) -> io::Result<()> {
+        let report = json!({
+            "version": REPORT_VERSION,
+            "root": self.root,
+            "algorithm": self.options.algorithm.as_str(),
+            "hash_version": self.options.hash_version.as_u64(),
+            "options": self.options.to_json(),
+            "entry_count": self.entry_count,
+            "total_bytes": self.total_bytes,
+            "tool": "paq",
+            "tool_version": self.tool_version,
+        });
+        serde_json::to_writer_pretty(writer, &report)?;
+        Ok(())
+    }
+
+    /// Read report from JSON.
+    ///
+    /// Fails if JSON is malformed, report version is newer than supported, or report is keyed
+    /// (see [`Report::read_json_keyed`]).
+    pub fn read_json<R: Read>(reader: R) -> io::Result<Report> {
+        read_json(reader, None)
+    }
+
+    /// Read report written using [`HashOptions::key`] from JSON, setting key on report options.
+    pub fn read_json_keyed<R: Read>(reader: R, key: [u8; 32]) -> io::Result<Report> {
+        read_json(reader, Some(key))
+    }
+}
+
+fn read_json<R: Read>(reader: R, key: Option<[u8; 32]>) -> io::Result<Report> {
+    let value: Value = serde_json::from_reader(reader)?;
+    let report = value
+        .as_object()
+        .ok_or_else(|| invalid_data("report must be a JSON object"))?;
+    let get_str = |field: &str| {
+        report
+            .get(field)
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid_data(format!("report field `{field}` must be a string")))
+    };
+    let get_u64 = |field: &str| {
+        report
+            .get(field)
+            .and_then(Value::as_u64)
+            .ok_or_else(|| invalid_data(format!("report field `{field}` must be an integer")))
+    };
+    let version = get_u64("version")?;
+    if version > REPORT_VERSION {
+        return Err(invalid_data(format!("report version {version} is not supported")));
+    }
+    let options = match report.get("options") {
+        Some(options) => HashOptions::from_json(options, key)?,
+        None => HashOptions::default(),
+    };
+    Ok(Report {
+        root: get_str("root")?.to_owned(),
+        options,
+        entry_count: get_u64("entry_count")?,
+        total_bytes: get_u64("total_bytes")?,
+        tool_version: get_str("tool_version")?.to_owned(),
+        errors: Vec::new(),
+    })
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
@@ -0,0 +1,199 @@
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    ffi::OsStr,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+use super::{
+    digest::{
+        to_hex,
+        Digest,
+    },
+    manifest::{
+        Entry,
+        EntryKind,
+        Manifest,
+    },
+    options::HashOptions,
+};
+
+
+/// Node of [`Tree`] with digest derived from its entry and children.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TreeNode {
+    entry: Entry,
+    digest: Digest,
+    children: Vec<TreeNode>,
+}
+
+impl TreeNode {
+    /// Manifest entry of node.
+    pub fn entry(&self) -> &Entry {
+        &self.entry
+    }
+
+    /// Path relative to source root using `/` separators.
+    pub fn path(&self) -> &Path {
+        &self.entry.path
+    }
+
+    /// Merkle digest; equals entry digest for files and symlinks.
+    pub fn digest(&self) -> &Digest {
+        &self.digest
+    }
+
+    /// Merkle digest as lowercase hexadecimal.
+    pub fn to_hex(&self) -> String {
+        to_hex(&self.digest)
+    }
+
+    /// Children sorted by path relative to node (empty for files and symlinks).
+    pub fn children(&self) -> &[TreeNode] {
+        &self.children
+    }
+}
+
+/// Merkle tree of source where each directory digest is derived from its children.
+///
+/// Unchanged subtrees keep their digest, so they can be compared or reused without descending.
+/// Directory digest is the digest of directory entry digest, followed by child file name (length-prefixed)
+/// and child digest for each child sorted by file name.
+///
+/// Entries whose parent directory is missing from manifest, e.g. below directory skipped on error, are children
+/// of their nearest ancestor in manifest, named by path relative to it instead of file name.
+///
+/// Entry digests cover path from source root, so subtree digests only match subtrees at the same path
+/// hashed using the same options; a subdirectory hashed as its own source has other digests.
+///
+/// ```
+/// use paq::HashOptions;
+///
+/// let source = std::path::PathBuf::from("example");
+/// let tree = HashOptions::new()
+///     .ignore_hidden(true)
+///     .tree(&source)
+///     .unwrap();
+///
+/// assert_eq!(tree.root(), "d7d25c9b2fdb7391e650085a985ad0d892c7f0dd5edd32c7ccdb4b0d1c34c430");
+/// assert!(tree.iter().any(|node| node.path() == std::path::Path::new("alpha.txt")));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tree {
+    root: String,
+    merkle_root: String,
+    node: TreeNode,
+}
+
+impl Tree {
+    /// Build tree from manifest entries.
+    ///
+    /// Entries without parent directory in manifest are attached to their nearest ancestor in manifest.
+    pub fn from_manifest(manifest: &Manifest) -> Self {
+        let options = manifest.options();
+        let paths: HashSet<&Path> = manifest.entries().iter().map(|entry| entry.path.as_path()).collect();
+        let mut children: HashMap<&Path, Vec<&Entry>> = HashMap::new();
+        let mut root_entry = None;
+        for entry in manifest.entries() {
+            match entry.path.parent() {
+                Some(parent) => {
+                    // source root is ancestor of every entry, even without root entry
+                    let ancestor = parent
+                        .ancestors()
+                        .find(|ancestor| paths.contains(ancestor))
+                        .unwrap_or(Path::new(""));
+                    children.entry(ancestor).or_default().push(entry);
+                }
+                None => root_entry = Some(entry),
+            }
+        }
+        // source without root entry (e.g. empty manifest) is an empty directory
+        let root_entry = root_entry.cloned().unwrap_or_else(|| Entry {
+            path: PathBuf::new(),
+            kind: EntryKind::Dir,
+            size: 0,
+            content: options.hasher().finalize(),
+            digest: options.hasher().finalize(),
+        });
+        let node = build_node(root_entry, &mut children, options);
+        Tree {
+            root: manifest.root().to_owned(),
+            merkle_root: options.label(&node.digest),
+            node,
+        }
+    }
+
+    /// Source root hash of flat scheme, equal to [`Manifest::root`].
+    pub fn root(&self) -> &str {
+        &self.root
+    }
+
+    /// Merkle root hash at algorithm digest length, prefixed as [`Tree::root`].
+    pub fn merkle_root(&self) -> &str {
+        &self.merkle_root
+    }
+
+    /// Root node of source.
+    pub fn node(&self) -> &TreeNode {
+        &self.node
+    }
+
+    /// Node at path relative to source root.
+    pub fn get(&self, path: &Path) -> Option<&TreeNode> {
+        let mut node = &self.node;
+        while node.path() != path {
+            node = node.children.iter().find(|child| path.starts_with(child.path()))?;
+        }
+        Some(node)
+    }
+
+    /// Iterate nodes depth-first, parents before children.
+    pub fn iter(&self) -> impl Iterator<Item = &TreeNode> {
+        let mut stack = vec![&self.node];
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.children.iter().rev());
+            Some(node)
+        })
+    }
+}
+
+fn build_node(entry: Entry, children: &mut HashMap<&Path, Vec<&Entry>>, options: &HashOptions) -> TreeNode {
+    let mut child_nodes: Vec<TreeNode> = children
+        .remove(entry.path.as_path())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|child| build_node(child.clone(), children, options))
+        .collect();
+    let name = |child: &TreeNode| child_name(&entry.path, child.path()).to_owned();
+    child_nodes.sort_by_cached_key(name);
+
+    let digest = if entry.kind == EntryKind::Dir {
+        let mut hasher = options.hasher();
+        hasher.update(&entry.digest);
+        for child in &child_nodes {
+            let name = child_name(&entry.path, child.path()).as_encoded_bytes();
+            hasher.update(&(name.len() as u64).to_le_bytes());
+            hasher.update(name);
+            hasher.update(&child.digest);
+        }
+        hasher.finalize()
+    } else {
+        entry.digest.clone()
+    };
+    TreeNode {
+        entry,
+        digest,
+        children: child_nodes,
+    }
+}
+
+/// Child path relative to parent, which is file name unless parent directories are missing from manifest.
+fn child_name<'a>(parent: &Path, child: &'a Path) -> &'a OsStr {
+    child.strip_prefix(parent).unwrap_or(child).as_os_str()
+}
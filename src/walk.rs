@@ -0,0 +1,491 @@
+use std::{
+    ffi::OsStr,
+    fs::{
+        self,
+        FileType,
+        Metadata,
+    },
+    io,
+    path::{
+        Path,
+        PathBuf,
+    },
+    str::FromStr,
+};
+
+use globset::{
+    GlobBuilder,
+    GlobSet,
+    GlobSetBuilder,
+};
+use ignore::{
+    gitignore::{
+        Gitignore,
+        GitignoreBuilder,
+    },
+    Match,
+};
+use walkdir::{
+    DirEntry,
+    IntoIter,
+    WalkDir,
+};
+
+use super::{
+    error::Error,
+    options::HashOptions,
+};
+
+
+/// Handling of symlinks whose target does not exist when following symlinks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DanglingSymlinks {
+    /// Fail with [`Error::DanglingSymlink`].
+    #[default]
+    Error,
+    /// Skip symlink.
+    Skip,
+    /// Hash symlink target path as if symlink was not followed.
+    Hash,
+}
+
+impl DanglingSymlinks {
+    /// Name used in options output.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DanglingSymlinks::Error => "error",
+            DanglingSymlinks::Skip => "skip",
+            DanglingSymlinks::Hash => "hash",
+        }
+    }
+}
+
+impl FromStr for DanglingSymlinks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(DanglingSymlinks::Error),
+            "skip" => Ok(DanglingSymlinks::Skip),
+            "hash" => Ok(DanglingSymlinks::Hash),
+            _ => Err(format!("unknown dangling symlink policy `{s}`")),
+        }
+    }
+}
+
+/// Ignore files read in every directory, ordered lowest to highest precedence.
+pub const IGNORE_FILE_NAMES: [&str; 3] = [".gitignore", ".ignore", ".paqignore"];
+
+#[inline]
+pub(crate) fn relative_path<'a>(root: &Path, path: &'a Path) -> &'a Path {
+    path.strip_prefix(root).unwrap_or(path)
+}
+
+#[inline]
+fn is_hidden(file_name: &OsStr) -> bool {
+    file_name
+        .as_encoded_bytes()
+        .split_first()
+        .is_some_and(|(first, rest)| *first == b'.' && !rest.is_empty())
+}
+
+/// File system entry yielded by [`Walker`].
+///
+/// File type is of symlink target when following symlinks.
+pub(crate) struct WalkEntry {
+    path: PathBuf,
+    file_type: FileType,
+}
+
+impl WalkEntry {
+    /// Entry of path without walking, using file type of symlink itself unless path is source root.
+    pub(crate) fn from_path(root: &Path, relative: &Path) -> io::Result<Self> {
+        let (path, metadata) = if relative.as_os_str().is_empty() {
+            // walker follows symlink of source root
+            (root.to_path_buf(), fs::metadata(root)?)
+        } else {
+            let path = root.join(relative);
+            let metadata = fs::symlink_metadata(&path)?;
+            (path, metadata)
+        };
+        Ok(WalkEntry {
+            path,
+            file_type: metadata.file_type(),
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    /// Metadata of symlink itself if not followed, otherwise of target.
+    pub(crate) fn metadata(&self) -> io::Result<Metadata> {
+        if self.file_type.is_symlink() {
+            fs::symlink_metadata(&self.path)
+        } else {
+            fs::metadata(&self.path)
+        }
+    }
+}
+
+impl From<DirEntry> for WalkEntry {
+    fn from(entry: DirEntry) -> Self {
+        WalkEntry {
+            file_type: entry.file_type(),
+            path: entry.into_path(),
+        }
+    }
+}
+
+/// Gitignore-style rules collected while descending into directories.
+struct IgnoreRules {
+    global: Gitignore,
+    // matchers of ancestor directories paired with directory depth (deepest last)
+    stack: Vec<(usize, Gitignore)>,
+}
+
+impl IgnoreRules {
+    fn new(root: &Path, global: bool) -> Self {
+        // global git excludes file is optional; unreadable or invalid patterns are skipped
+        let global = if global {
+            GitignoreBuilder::new(root).build_global().0
+        } else {
+            Gitignore::empty()
+        };
+        IgnoreRules {
+            global,
+            stack: Vec::new(),
+        }
+    }
+
+    fn is_ignored(&mut self, path: &Path, depth: usize, is_dir: bool) -> bool {
+        // source root is never ignored
+        if depth == 0 {
+            return false;
+        }
+        // drop matchers of directories that are not ancestors of entry
+        while self.stack.last().is_some_and(|(stack_depth, _)| *stack_depth >= depth) {
+            self.stack.pop();
+        }
+        // deepest matching directory takes precedence over ancestors and global excludes
+        self.stack
+            .iter()
+            .rev()
+            .map(|(_, gitignore)| gitignore)
+            .chain([&self.global])
+            .map(|gitignore| gitignore.matched(path, is_dir))
+            .find(|matched| !matched.is_none())
+            .is_some_and(|matched| matches!(matched, Match::Ignore(_)))
+    }
+
+    fn push_dir(&mut self, root: &Path, dir: &Path, depth: usize) -> Result<(), Error> {
+        let mut builder = GitignoreBuilder::new(dir);
+        for file_name in IGNORE_FILE_NAMES {
+            let path = dir.join(file_name);
+            if !path.is_file() {
+                continue;
+            }
+            if let Some(err) = builder.add(&path) {
+                return Err(Error::Ignore {
+                    path: relative_path(root, &path).to_path_buf(),
+                    source: io::Error::other(err),
+                });
+            }
+        }
+        let gitignore = builder.build().map_err(|err| Error::Ignore {
+            path: relative_path(root, dir).to_path_buf(),
+            source: io::Error::other(err),
+        })?;
+        if !gitignore.is_empty() {
+            self.stack.push((depth, gitignore));
+        }
+        Ok(())
+    }
+}
+
+/// Include and exclude glob patterns matched against path relative to source root.
+///
+/// Patterns without `/` match file name at any depth, otherwise full relative path.
+/// Exclude patterns ending in `/**` also match the directory itself, as in gitignore, so it is pruned.
+pub(crate) struct PathPatterns {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    // directories matched by exclude patterns ending in `/**`
+    exclude_dirs: GlobSet,
+}
+
+impl PathPatterns {
+    pub(crate) fn new(include: &[String], exclude: &[String]) -> Result<Self, Error> {
+        let include = if include.is_empty() {
+            None
+        } else {
+            Some(build_glob_set(include)?)
+        };
+        let exclude_dirs: Vec<String> = exclude
+            .iter()
+            .filter_map(|pattern| pattern.strip_suffix("/**"))
+            .filter(|prefix| !prefix.is_empty())
+            .map(|prefix| {
+                // prefix keeps matching as path, not file name at any depth
+                if prefix.contains('/') { prefix.to_owned() } else { format!("/{prefix}") }
+            })
+            .collect();
+        Ok(PathPatterns {
+            include,
+            exclude: build_glob_set(exclude)?,
+            exclude_dirs: build_glob_set(&exclude_dirs)?,
+        })
+    }
+
+    pub(crate) fn is_excluded(&self, relative: &Path, is_dir: bool) -> bool {
+        if self.exclude.is_match(relative) || (is_dir && self.exclude_dirs.is_match(relative)) {
+            return true;
+        }
+        // directories are traversed for included descendants
+        !is_dir && self.include.as_ref().is_some_and(|include| !include.is_match(relative))
+    }
+}
+
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet, Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = if pattern.contains('/') {
+            pattern.trim_start_matches('/').to_owned()
+        } else {
+            format!("**/{pattern}")
+        };
+        let glob = GlobBuilder::new(&glob)
+            .literal_separator(true)
+            .build()
+            .map_err(|source| Error::Pattern {
+                pattern: pattern.to_owned(),
+                source,
+            })?;
+        builder.add(glob);
+    }
+    builder.build().map_err(|source| Error::Pattern {
+        pattern: patterns.join(" "),
+        source,
+    })
+}
+
+/// File system walker applying [`HashOptions`] filters.
+///
+/// Filtered directories are pruned without traversing their contents.
+/// Directory loops are detected by (device, inode) of ancestors, which covers symlinks and bind mounts.
+pub(crate) struct Walker {
+    root: PathBuf,
+    iter: IntoIter,
+    // depth of walked path below source root
+    depth: usize,
+    ignore_hidden: bool,
+    patterns: PathPatterns,
+    ignore_rules: Option<IgnoreRules>,
+    follow_symlinks: bool,
+    dangling_symlinks: DanglingSymlinks,
+    // (depth, device, inode) of ancestor directories (deepest last)
+    #[cfg(target_family = "unix")]
+    ancestors: Vec<(usize, u64, u64)>,
+}
+
+impl Walker {
+    pub(crate) fn new(root: &Path, options: &HashOptions) -> Result<Self, Error> {
+        Ok(Walker {
+            root: root.to_path_buf(),
+            iter: WalkDir::new(root).follow_links(options.follow_symlinks).into_iter(),
+            depth: 0,
+            ignore_hidden: options.ignore_hidden,
+            patterns: PathPatterns::new(&options.include, &options.exclude)?,
+            ignore_rules: options.ignore_files.then(|| IgnoreRules::new(root, options.global_ignore)),
+            follow_symlinks: options.follow_symlinks,
+            dangling_symlinks: options.dangling_symlinks,
+            #[cfg(target_family = "unix")]
+            ancestors: Vec::new(),
+        })
+    }
+
+    /// Walker of entries at and below relative path, as if walking source root.
+    ///
+    /// Ancestors of path are checked against filters (and their ignore files read) without walking
+    /// their other entries. Returns `None` if path or any ancestor is filtered, missing or not a directory.
+    /// Symlinks must not be followed, as loops through ancestors of path would not be detected.
+    pub(crate) fn subtree(root: &Path, relative: &Path, options: &HashOptions) -> Result<Option<Self>, Error> {
+        debug_assert!(!options.follow_symlinks && !relative.as_os_str().is_empty());
+        let mut walker = Walker::new(root, options)?;
+        let mut dir = root.to_path_buf();
+        let mut names = relative.iter().peekable();
+        // walker yields source root under its own path if it has no file name
+        let mut file_name = root.file_name().unwrap_or(root.as_os_str());
+        loop {
+            // walker follows symlink of source root only
+            let metadata = if walker.depth == 0 { fs::metadata(&dir) } else { fs::symlink_metadata(&dir) };
+            let metadata = match metadata {
+                Ok(metadata) if metadata.is_dir() => metadata,
+                Ok(_) => return Ok(None),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+                Err(source) => {
+                    return Err(Error::Metadata {
+                        path: walker.relative(&dir),
+                        source,
+                    })
+                }
+            };
+            if walker.is_filtered(&dir, file_name, walker.depth, true) {
+                return Ok(None);
+            }
+            walker.check_loop(&dir, walker.depth, &metadata)?;
+            if let Some(rules) = walker.ignore_rules.as_mut() {
+                rules.push_dir(&walker.root, &dir, walker.depth)?;
+            }
+            walker.depth += 1;
+            file_name = names.next().unwrap_or_default();
+            dir.push(file_name);
+            if names.peek().is_none() {
+                break;
+            }
+        }
+        match fs::symlink_metadata(&dir) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(source) => {
+                return Err(Error::Metadata {
+                    path: walker.relative(&dir),
+                    source,
+                })
+            }
+        }
+        walker.iter = WalkDir::new(&dir).follow_root_links(false).into_iter();
+        Ok(Some(walker))
+    }
+
+    fn relative(&self, path: &Path) -> PathBuf {
+        relative_path(&self.root, path).to_path_buf()
+    }
+
+    /// Handle traversal error, returning entry if error is a dangling symlink to hash.
+    fn walk_error(&self, e: walkdir::Error) -> Option<Result<WalkEntry, Error>> {
+        let path = e.path().unwrap_or(&self.root).to_path_buf();
+        if e.loop_ancestor().is_some() {
+            return Some(Err(Error::Loop { path: self.relative(&path) }));
+        }
+        let is_dangling = self.follow_symlinks
+            && e.io_error().is_some_and(|e| e.kind() == io::ErrorKind::NotFound)
+            && fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_symlink());
+        if !is_dangling {
+            return Some(Err(Error::Walk {
+                path: self.relative(&path),
+                source: e.into(),
+            }));
+        }
+        match self.dangling_symlinks {
+            DanglingSymlinks::Error => Some(Err(Error::DanglingSymlink { path: self.relative(&path) })),
+            DanglingSymlinks::Skip => None,
+            DanglingSymlinks::Hash => match fs::symlink_metadata(&path) {
+                Ok(metadata) => Some(Ok(WalkEntry {
+                    path,
+                    file_type: metadata.file_type(),
+                })),
+                Err(source) => Some(Err(Error::Metadata {
+                    path: self.relative(&path),
+                    source,
+                })),
+            },
+        }
+    }
+
+    /// Fail if directory is its own ancestor (following symlinks relies on walker loop detection).
+    #[cfg(target_family = "unix")]
+    fn check_loop(&mut self, path: &Path, depth: usize, metadata: &Metadata) -> Result<(), Error> {
+        use std::os::unix::fs::MetadataExt;
+
+        while self.ancestors.last().is_some_and(|(ancestor_depth, _, _)| *ancestor_depth >= depth) {
+            self.ancestors.pop();
+        }
+        let id = (metadata.dev(), metadata.ino());
+        if self.ancestors.iter().any(|(_, dev, ino)| (*dev, *ino) == id) {
+            return Err(Error::Loop { path: self.relative(path) });
+        }
+        self.ancestors.push((depth, id.0, id.1));
+        Ok(())
+    }
+
+    #[cfg(not(target_family = "unix"))]
+    fn check_loop(&mut self, _: &Path, _: usize, _: &Metadata) -> Result<(), Error> {
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    fn check_dir_loop(&mut self, entry: &DirEntry) -> Result<(), Error> {
+        // stat is needed once per directory (not per file): inode listed by parent directory is that of mount
+        // point rather than mounted directory, so bind mounts looping to ancestors would go undetected
+        let metadata = entry.metadata().map_err(|source| Error::Metadata {
+            path: self.relative(entry.path()),
+            source: source.into(),
+        })?;
+        self.check_loop(entry.path(), self.depth + entry.depth(), &metadata)
+    }
+
+    #[cfg(not(target_family = "unix"))]
+    fn check_dir_loop(&mut self, _: &DirEntry) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn is_filtered(&mut self, path: &Path, file_name: &OsStr, depth: usize, is_dir: bool) -> bool {
+        if self.ignore_hidden && is_hidden(file_name) {
+            return true;
+        }
+        // source root is never excluded by patterns
+        if depth > 0 {
+            let relative = relative_path(&self.root, path);
+            if self.patterns.is_excluded(relative, is_dir) {
+                return true;
+            }
+        }
+        self.ignore_rules
+            .as_mut()
+            .is_some_and(|rules| rules.is_ignored(path, depth, is_dir))
+    }
+}
+
+impl Iterator for Walker {
+    type Item = Result<WalkEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = match self.iter.next()? {
+                Ok(entry) => entry,
+                Err(e) => match self.walk_error(e) {
+                    Some(entry) => return Some(entry),
+                    None => continue,
+                },
+            };
+            let is_dir = entry.file_type().is_dir();
+            let depth = self.depth + entry.depth();
+            if self.is_filtered(entry.path(), entry.file_name(), depth, is_dir) {
+                if is_dir {
+                    self.iter.skip_current_dir();
+                }
+                continue;
+            }
+            if is_dir {
+                if !self.follow_symlinks {
+                    if let Err(e) = self.check_dir_loop(&entry) {
+                        // directory is not descended into, in case error is skipped or recorded
+                        self.iter.skip_current_dir();
+                        return Some(Err(e));
+                    }
+                }
+                if let Some(rules) = self.ignore_rules.as_mut() {
+                    if let Err(e) = rules.push_dir(&self.root, entry.path(), depth) {
+                        return Some(Err(e));
+                    }
+                }
+            }
+            return Some(Ok(entry.into()));
+        }
+    }
+}
@@ -0,0 +1,321 @@
+use std::{
+    collections::{
+        BTreeSet,
+        HashMap,
+    },
+    io,
+    ops::ControlFlow,
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::mpsc::{
+        self,
+        RecvTimeoutError,
+    },
+    time::Duration,
+};
+
+use notify::{
+    EventKind,
+    RecursiveMode,
+    Watcher,
+};
+
+use super::{
+    error::{
+        EntryError,
+        Error,
+    },
+    error_entries,
+    hash_entries,
+    hash_walk,
+    manifest::{
+        Entry,
+        Manifest,
+    },
+    manifest_entry,
+    metadata::MetadataEncoder,
+    options::HashOptions,
+    source_path,
+    walk::{
+        relative_path,
+        WalkEntry,
+        Walker,
+        IGNORE_FILE_NAMES,
+    },
+};
+
+
+/// Source root hash recomputed by [`HashOptions::watch`] after changes settled.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WatchEvent {
+    manifest: Manifest,
+    changed: Vec<PathBuf>,
+}
+
+impl WatchEvent {
+    /// Source root hash, see [`HashOptions::hash`].
+    pub fn root(&self) -> &str {
+        self.manifest.root()
+    }
+
+    /// Manifest of source with per-entry digests.
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Changed paths relative to source root, sorted (empty for initial hash).
+    ///
+    /// Paths are reported by file system notifications, so may include filtered or removed paths.
+    /// Empty path means notifications were lost (e.g. queue overflow), so whole source was hashed again.
+    pub fn changed(&self) -> &[PathBuf] {
+        &self.changed
+    }
+}
+
+/// Watch source, calling handler with initial hash and again once changes settle for debounce duration.
+///
+/// Only changed paths (with their descendants) are walked and hashed again; digests of other entries are
+/// kept in memory. Parent directories of changed paths are hashed again if metadata is selected, and
+/// directories of changed ignore files are walked again. Whole source is walked again if notifications were
+/// lost, or if following symlinks (as loops through ancestors of changed paths would not be detected), but
+/// only content of changed entries is read. Errors while hashing are passed to handler and watching continues.
+pub(crate) fn watch<F>(source: &Path, options: &HashOptions, debounce: Duration, mut handler: F) -> Result<(), Error>
+where
+    F: FnMut(Result<WatchEvent, Error>) -> ControlFlow<()>,
+{
+    options.validate()?;
+    if options.archive && source.is_file() {
+        return Err(Error::Archive {
+            path: PathBuf::new(),
+            reason: "watching archives is not supported".to_owned(),
+        });
+    }
+    let metadata = MetadataEncoder::new(options);
+    // notifications report absolute paths, so changed paths are relative to canonical root
+    let root = source.canonicalize().map_err(|source| Error::Watch {
+        path: PathBuf::new(),
+        source,
+    })?;
+    let (sender, receiver) = mpsc::channel();
+    // watch before initial hash so that changes while hashing are not missed
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = sender.send(event);
+    })
+    .map_err(|e| watch_error(&root, e))?;
+    watcher
+        .watch(&root, RecursiveMode::Recursive)
+        .map_err(|e| watch_error(&root, e))?;
+
+    let mut state = WatchState::new(source, options, &metadata)?;
+    let event = WatchEvent {
+        manifest: state.manifest(options),
+        changed: Vec::new(),
+    };
+    if handler(Ok(event)).is_break() {
+        return Ok(());
+    }
+
+    // changes that failed to hash are hashed again with next change
+    let mut pending = BTreeSet::new();
+    loop {
+        // block until first change, then collect changes until quiet for debounce duration
+        let mut changed = BTreeSet::new();
+        let mut next = receiver.recv().map_err(|_| RecvTimeoutError::Disconnected);
+        loop {
+            match next {
+                Ok(Ok(event)) => {
+                    if event.need_rescan() || (event.paths.is_empty() && !matches!(event.kind, EventKind::Access(_))) {
+                        // events were dropped (e.g. queue overflow), so whole source may have changed
+                        changed.insert(PathBuf::new());
+                    } else if !matches!(event.kind, EventKind::Access(_)) {
+                        // reads, including hashing source, are not changes
+                        changed.extend(event.paths.iter().map(|path| relative_path(&root, path).to_path_buf()));
+                    }
+                }
+                Ok(Err(e)) => {
+                    if handler(Err(watch_error(&root, e))).is_break() {
+                        return Ok(());
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+            next = receiver.recv_timeout(debounce);
+        }
+        if changed.is_empty() {
+            continue;
+        }
+
+        pending.extend(changed.iter().cloned());
+        let event = state.update(source, options, &metadata, &pending).map(|_| {
+            pending.clear();
+            WatchEvent {
+                manifest: state.manifest(options),
+                changed: changed.into_iter().collect(),
+            }
+        });
+        if handler(event).is_break() {
+            return Ok(());
+        }
+    }
+}
+
+/// Hashed entries and errors of watched source, keyed by path relative to source root.
+///
+/// Hashed entries are kept apart from error entries, so that failed entries are hashed again next change.
+struct WatchState {
+    entries: HashMap<PathBuf, Entry>,
+    errors: Vec<EntryError>,
+}
+
+impl WatchState {
+    fn new(source: &Path, options: &HashOptions, metadata: &MetadataEncoder) -> Result<Self, Error> {
+        let (entries, errors) = hash_entries(source, options, |root, entry| {
+            manifest_entry(root, entry, options, metadata, None)
+        })?;
+        Ok(WatchState {
+            entries: entries.into_iter().map(|entry| (entry.path.clone(), entry)).collect(),
+            errors,
+        })
+    }
+
+    /// Manifest of hashed entries, with errors recorded if options record errors.
+    fn manifest(&self, options: &HashOptions) -> Manifest {
+        let entries = self
+            .entries
+            .values()
+            .cloned()
+            .chain(error_entries(&self.errors, options))
+            .collect();
+        Manifest::new(entries, options.clone()).with_errors(self.errors.clone())
+    }
+
+    /// Hash changed paths again, leaving state unchanged if hashing fails.
+    fn update(
+        &mut self,
+        source: &Path,
+        options: &HashOptions,
+        metadata: &MetadataEncoder,
+        changed: &BTreeSet<PathBuf>,
+    ) -> Result<(), Error> {
+        // failed entries are hashed again with any change
+        let mut walks: BTreeSet<PathBuf> = changed
+            .iter()
+            .chain(self.errors.iter().map(|error| &error.path))
+            .cloned()
+            .collect();
+        if options.ignore_files {
+            // ignore files filter entries anywhere below their directory
+            walks.extend(
+                changed
+                    .iter()
+                    .filter(|path| {
+                        path.file_name()
+                            .is_some_and(|name| IGNORE_FILE_NAMES.iter().any(|ignore| name == *ignore))
+                    })
+                    .map(|path| path.parent().unwrap_or(Path::new("")).to_path_buf()),
+            );
+        }
+        // adding, removing or renaming entries changes metadata of parent directory, which is not reported
+        let dirs: BTreeSet<PathBuf> = if metadata.is_empty() {
+            BTreeSet::new()
+        } else {
+            changed.iter().filter_map(|path| path.parent()).map(Path::to_path_buf).collect()
+        };
+
+        if walks.contains(Path::new("")) {
+            // empty path marks whole source as changed, not only source root entry
+            *self = WatchState::new(source, options, metadata)?;
+            return Ok(());
+        }
+        if options.follow_symlinks {
+            let stale: BTreeSet<PathBuf> = walks.into_iter().chain(dirs).collect();
+            let (entries, errors) = hash_entries(source, options, |root, entry| {
+                let relative = source_path(root, entry)?;
+                match self.entries.get(&relative) {
+                    Some(previous) if !is_changed(&relative, &stale) => Ok(previous.clone()),
+                    _ => manifest_entry(root, entry, options, metadata, None),
+                }
+            })?;
+            self.entries = entries.into_iter().map(|entry| (entry.path.clone(), entry)).collect();
+            self.errors = errors;
+            return Ok(());
+        }
+
+        // descendants of walked paths are walked with them
+        let walks: BTreeSet<PathBuf> = walks
+            .iter()
+            .filter(|path| !has_changed_ancestor(path, &walks))
+            .cloned()
+            .collect();
+        let mut entries = Vec::new();
+        let mut errors = Vec::new();
+        for path in &walks {
+            if let Some(walker) = Walker::subtree(source, path, options)? {
+                let (walked, walk_errors) = hash_walk(source, walker, options, |root, entry| {
+                    manifest_entry(root, entry, options, metadata, None)
+                })?;
+                entries.extend(walked);
+                errors.extend(walk_errors);
+            }
+        }
+        // directories already hashed are only hashed again if walked
+        let dirs: Vec<&PathBuf> = dirs
+            .iter()
+            .filter(|dir| !is_changed(dir, &walks) && self.entries.contains_key(*dir))
+            .collect();
+        for dir in &dirs {
+            let result = WalkEntry::from_path(source, dir)
+                .map_err(|source| Error::Metadata {
+                    path: dir.to_path_buf(),
+                    source,
+                })
+                .and_then(|entry| manifest_entry(source, &entry, options, metadata, None));
+            match result {
+                Ok(entry) => entries.push(entry),
+                Err(e) if options.on_error.catches(&e) => errors.push(EntryError::from(&e)),
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.entries.retain(|path, _| !is_changed(path, &walks) && !dirs.contains(&path));
+        self.errors.retain(|error| !is_changed(&error.path, &walks) && !dirs.contains(&&error.path));
+        self.entries.extend(entries.into_iter().map(|entry| (entry.path.clone(), entry)));
+        // entries of failed paths are dropped, as when walking whole source
+        for error in &errors {
+            self.entries.remove(&error.path);
+        }
+        self.errors.extend(errors);
+        self.errors.sort_by(|a, b| a.path.cmp(&b.path));
+        self.errors.dedup_by(|a, b| a.path == b.path);
+        Ok(())
+    }
+}
+
+/// Path or any ancestor below source root changed (e.g. directory replaced or moved).
+fn is_changed(path: &Path, changed: &BTreeSet<PathBuf>) -> bool {
+    changed.contains(path) || has_changed_ancestor(path, changed)
+}
+
+fn has_changed_ancestor(path: &Path, changed: &BTreeSet<PathBuf>) -> bool {
+    path.ancestors()
+        .skip(1)
+        .filter(|ancestor| !ancestor.as_os_str().is_empty())
+        .any(|ancestor| changed.contains(ancestor))
+}
+
+fn watch_error(root: &Path, e: notify::Error) -> Error {
+    let path = e
+        .paths
+        .first()
+        .map(|path| relative_path(root, path).to_path_buf())
+        .unwrap_or_default();
+    let message = e.to_string();
+    let source = match e.kind {
+        notify::ErrorKind::Io(source) => source,
+        _ => io::Error::other(message),
+    };
+    Error::Watch { path, source }
+}
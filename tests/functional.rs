@@ -3,7 +3,14 @@ mod utils;
 mod lib {
     use std::{
         env,
-        path::PathBuf,
+        fs,
+        iter,
+        path::{
+            Path,
+            PathBuf,
+        },
+        thread,
+        time::Duration,
     };
 
     use crate::utils::TempDir;
@@ -111,6 +118,138 @@ mod lib {
         assert_eq!(&hash_not_ignored[..], expectation);
     }
 
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn it_hashes_directory_following_symlinks() {
+        let dir = TempDir::new("it_hashes_directory_following_symlinks").unwrap();
+        let target_dir = TempDir::new("it_hashes_directory_following_symlinks/target").unwrap();
+        target_dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        dir.new_symlink("symlink", PathBuf::from("target")).unwrap();
+        let source = dir.path().canonicalize().unwrap();
+
+        let manifest = paq::HashOptions::new()
+            .follow_symlinks(true)
+            .manifest(&source)
+            .unwrap();
+        let entries: Vec<(&str, paq::EntryKind)> = manifest.entries()
+            .iter()
+            .map(|entry| (entry.path.to_str().unwrap(), entry.kind))
+            .collect();
+        assert_eq!(entries, vec![
+            ("", paq::EntryKind::Dir),
+            ("symlink", paq::EntryKind::Dir),
+            ("symlink/alpha", paq::EntryKind::File),
+            ("target", paq::EntryKind::Dir),
+            ("target/alpha", paq::EntryKind::File),
+        ]);
+        assert_eq!(manifest.entries()[2].content, manifest.entries()[4].content);
+
+        let hash_not_followed = paq::HashOptions::new().hash(&source).unwrap();
+        assert_ne!(manifest.root(), hash_not_followed.as_str());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn it_fails_following_symlink_loop() {
+        let dir = TempDir::new("it_fails_following_symlink_loop").unwrap();
+        let nested_dir = TempDir::new("it_fails_following_symlink_loop/nested").unwrap();
+        nested_dir.new_symlink("parent", PathBuf::from("..")).unwrap();
+        let source = dir.path().canonicalize().unwrap();
+
+        let error = paq::HashOptions::new()
+            .follow_symlinks(true)
+            .hash(&source)
+            .unwrap_err();
+        assert!(matches!(error, paq::Error::Loop { .. }));
+        assert!(paq::HashOptions::new().hash(&source).is_ok());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn it_hashes_dangling_symlink_using_policy() {
+        let dir = TempDir::new("it_hashes_dangling_symlink_using_policy").unwrap();
+        dir.new_symlink("symlink", PathBuf::from("missing")).unwrap();
+        let source = dir.path().canonicalize().unwrap();
+        let options = paq::HashOptions::new().follow_symlinks(true);
+
+        let error = options.clone().hash(&source).unwrap_err();
+        assert!(matches!(error, paq::Error::DanglingSymlink { .. }));
+        assert_eq!(error.path(), PathBuf::from("symlink").as_path());
+
+        let hash_skipped = options.clone()
+            .dangling_symlinks(paq::DanglingSymlinks::Skip)
+            .hash(&source)
+            .unwrap();
+        assert_eq!(&hash_skipped[..], "82878ed8a480ee41775636820e05a934ca5c747223ca64306658ee5982e6c227");
+
+        let hash_marked = options
+            .dangling_symlinks(paq::DanglingSymlinks::Hash)
+            .hash(&source)
+            .unwrap();
+        let hash_not_followed = paq::HashOptions::new().hash(&source).unwrap();
+        assert_eq!(hash_marked, hash_not_followed);
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn it_skips_or_records_entries_that_fail() {
+        let dir = TempDir::new("it_skips_or_records_entries_that_fail").unwrap();
+        dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        dir.new_symlink("symlink", PathBuf::from("missing")).unwrap();
+        let source = dir.path().canonicalize().unwrap();
+        let options = paq::HashOptions::new().follow_symlinks(true);
+
+        let error = options.clone()
+            .on_error(paq::OnError::Fail)
+            .hash(&source)
+            .unwrap_err();
+        assert!(matches!(error, paq::Error::DanglingSymlink { .. }));
+
+        // skipped entries hash as if they did not exist
+        let skipped = options.clone().on_error(paq::OnError::Skip).manifest(&source).unwrap();
+        let hash_without = options.clone()
+            .dangling_symlinks(paq::DanglingSymlinks::Skip)
+            .hash(&source)
+            .unwrap();
+        assert_eq!(skipped.root(), hash_without);
+        assert_eq!(skipped.errors().len(), 1);
+        assert_eq!(skipped.errors()[0].path, PathBuf::from("symlink"));
+        assert_eq!(skipped.errors()[0].kind, "dangling_symlink");
+
+        // recorded entries are typed error markers, so root differs from any run without errors
+        let options = options.on_error(paq::OnError::Record);
+        let recorded = options.manifest(&source).unwrap();
+        assert_eq!(recorded.errors(), skipped.errors());
+        // errors are reported without content digests of manifest
+        let report = options.report(&source).unwrap();
+        assert_eq!(report.root(), recorded.root());
+        assert_eq!(report.errors(), recorded.errors());
+        assert_ne!(recorded.root(), skipped.root());
+        assert_ne!(recorded.root(), paq::HashOptions::new().hash(&source).unwrap());
+        assert_eq!(recorded.root(), options.hash(&source).unwrap());
+        assert_eq!(
+            recorded.root(),
+            options.clone().parallelism(paq::Parallelism::Sequential).hash(&source).unwrap()
+        );
+        let marker = recorded.entries().iter().find(|entry| entry.path == Path::new("symlink")).unwrap();
+        assert_eq!(marker.kind, paq::EntryKind::Error);
+
+        // markers round trip through manifest JSON
+        let mut json = Vec::new();
+        recorded.write_json(&mut json).unwrap();
+        let read = paq::Manifest::read_json(json.as_slice()).unwrap();
+        assert_eq!(read.root(), recorded.root());
+        assert_eq!(read.options(), recorded.options());
+        assert!(read.errors().is_empty());
+
+        // errors of source root are never skipped
+        let error = paq::HashOptions::new()
+            .on_error(paq::OnError::Skip)
+            .hash(&source.join("missing"))
+            .unwrap_err();
+        assert!(matches!(error, paq::Error::Walk { .. }));
+    }
+
     #[test]
     fn it_hashes_directory_with_ignored_file() {
         let expectation_not_ignored =
@@ -183,131 +322,2139 @@ mod lib {
             assert_eq!(&hash_not_ignored[..], expectation);
         }
     }
-}
 
-// added allow deprecated attribute due to cargo_bin notice without a resolution
-#[allow(deprecated)]
-mod bin {
-    use crate::utils::TempDir;
-    use assert_cmd::{
-        cargo::cargo_bin,
-        Command,
-    };
-    use std::path::PathBuf;
+    #[test]
+    fn it_hashes_directory_using_options() {
+        let expectation_not_ignored =
+            "e383192a5ef45576817b4222e455e3d538ae3bab279a62c0a8b67279ad007072";
+        let expectation_ignored =
+            "82878ed8a480ee41775636820e05a934ca5c747223ca64306658ee5982e6c227";
+
+        let file_name = ".ignored";
+        let file_contents = ".ignored-body".as_bytes();
+        let dir = TempDir::new("it_hashes_directory_using_options").unwrap();
+        dir.new_file(file_name, file_contents).unwrap();
+        let source = dir.path().canonicalize().unwrap();
+
+        let hash_ignored = paq::HashOptions::new()
+            .ignore_hidden(true)
+            .hash(&source)
+            .unwrap();
+        assert_eq!(&hash_ignored[..], expectation_ignored);
+        let hash_not_ignored = paq::HashOptions::new().hash(&source).unwrap();
+        assert_eq!(&hash_not_ignored[..], expectation_not_ignored);
+    }
 
     #[test]
-    fn it_outputs_file_hash_using_default_short_arg() {
-        let expectation = "48ec422c86fd2aa1ac182f832c10cf6cb07e4b89d88b83a7794bd8773460072c";
+    fn it_hashes_directory_with_manifest() {
+        let expectation = "7ed5febd35e277763cdfc3e4bee136acf38e48e9462972a732cc4d348a37d653";
 
         let file_name = "alpha";
         let file_contents = "alpha-body".as_bytes();
-        let hash_file_name = "alpha.paq";
-        let dir = TempDir::new("it_outputs_file_hash_using_default_short_arg").unwrap();
+        let dir = TempDir::new("it_hashes_directory_with_manifest").unwrap();
         dir.new_file(file_name, file_contents).unwrap();
-        let source = dir.path().join(file_name);
+        let source = dir.path().canonicalize().unwrap();
 
-        let mut cmd = Command::new(cargo_bin!("paq"));
-        let assert = cmd
-            .arg(source.as_os_str().to_str().unwrap())
-            .arg("-o")
-            .assert();
-        assert
-            .code(0)
-            .stdout(format!("{expectation}\n"))
-            .success();
+        let manifest = paq::hash_source_with_manifest(&source, false).unwrap();
+        assert_eq!(manifest.root(), expectation);
+        assert_eq!(manifest.derive_root().as_str(), expectation);
 
-        let file_hash = dir.read_file(hash_file_name).unwrap();
-        assert_eq!(
-            file_hash.as_slice(),
-            format!("\"{expectation}\"").as_bytes()
-        );
+        let entries = manifest.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].path, PathBuf::from(""));
+        assert_eq!(entries[0].kind, paq::EntryKind::Dir);
+        assert_eq!(entries[1].path, PathBuf::from(file_name));
+        assert_eq!(entries[1].kind, paq::EntryKind::File);
+        assert_eq!(entries[1].size, file_contents.len() as u64);
+        assert_eq!(entries[1].content.as_slice(), blake3::hash(file_contents).as_bytes());
     }
 
     #[test]
-    fn it_outputs_file_hash_using_short_arg() {
-        let expectation = "48ec422c86fd2aa1ac182f832c10cf6cb07e4b89d88b83a7794bd8773460072c";
+    fn it_reads_manifest_written_as_json() {
+        let file_name = "alpha";
+        let file_contents = "alpha-body".as_bytes();
+        let dir = TempDir::new("it_reads_manifest_written_as_json").unwrap();
+        dir.new_file(file_name, file_contents).unwrap();
+        let source = dir.path().canonicalize().unwrap();
+
+        let manifest = paq::hash_source_with_manifest(&source, false).unwrap();
+        let mut json = Vec::new();
+        manifest.write_json(&mut json).unwrap();
+        let manifest_read = paq::Manifest::read_json(json.as_slice()).unwrap();
+        assert_eq!(manifest_read, manifest);
+
+        let digest = blake3::Hash::from_slice(&manifest.entries()[1].digest).unwrap().to_hex();
+        let json = String::from_utf8(json).unwrap().replace(digest.as_str(), &"0".repeat(64));
+        assert!(paq::Manifest::read_json(json.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn it_verifies_source_against_expected_hash() {
+        let expectation = "7ed5febd35e277763cdfc3e4bee136acf38e48e9462972a732cc4d348a37d653";
 
         let file_name = "alpha";
         let file_contents = "alpha-body".as_bytes();
-        let hash_file_name = "custom.paq";
-        let dir = TempDir::new("it_outputs_file_hash_using_short_arg").unwrap();
+        let dir = TempDir::new("it_verifies_source_against_expected_hash").unwrap();
         dir.new_file(file_name, file_contents).unwrap();
-        let source = dir.path().join(file_name);
-        let mut output = PathBuf::from(&source).parent().unwrap().to_path_buf();
-        output.push(hash_file_name);
+        let source = dir.path().canonicalize().unwrap();
 
-        let mut cmd = Command::new(cargo_bin!("paq"));
-        let assert = cmd
-            .arg(source.as_os_str().to_str().unwrap())
-            .arg(format!("-o={}", output.as_os_str().to_str().unwrap()))
-            .assert();
-        assert
-            .code(0)
-            .stdout(format!("{expectation}\n"))
-            .success();
+        assert!(paq::verify_source(&source, expectation).unwrap());
+        assert!(paq::verify_source(&source, &format!("{expectation}\n")).unwrap());
+        assert!(!paq::verify_source(&source, &expectation[..63]).unwrap());
+        assert!(!paq::HashOptions::new()
+            .ignore_hidden(true)
+            .verify(&source, "82878ed8a480ee41775636820e05a934ca5c747223ca64306658ee5982e6c227")
+            .unwrap());
+    }
+
+    #[test]
+    fn it_hashes_ambiguous_entries_distinctly_using_hash_version_2() {
+        let ab_dir = TempDir::new("it_hashes_ambiguous_entries_distinctly_using_hash_version_2/ab").unwrap();
+        ab_dir.new_file("ab", "c".as_bytes()).unwrap();
+        let a_dir = TempDir::new("it_hashes_ambiguous_entries_distinctly_using_hash_version_2/a").unwrap();
+        a_dir.new_file("a", "bc".as_bytes()).unwrap();
+        let file_dir = TempDir::new("it_hashes_ambiguous_entries_distinctly_using_hash_version_2/file").unwrap();
+        file_dir.new_file("x", "".as_bytes()).unwrap();
+        let dir_dir = TempDir::new("it_hashes_ambiguous_entries_distinctly_using_hash_version_2/dir/x").unwrap();
+        let dir_dir = dir_dir.path().parent().unwrap();
+
+        let v1 = paq::HashOptions::new();
+        let v2 = paq::HashOptions::new().hash_version(paq::HashVersion::V2);
+        let hash = |options: &paq::HashOptions, source: &Path| {
+            options.hash(&source.canonicalize().unwrap()).unwrap()
+        };
+
+        assert_eq!(hash(&v1, ab_dir.path()), hash(&v1, a_dir.path()));
+        assert_eq!(hash(&v1, file_dir.path()), hash(&v1, dir_dir));
+        assert_ne!(hash(&v2, ab_dir.path()), hash(&v2, a_dir.path()));
+        assert_ne!(hash(&v2, file_dir.path()), hash(&v2, dir_dir));
+
+        let expected = hash(&v2, ab_dir.path());
+        assert!(expected.starts_with("paq2:"));
+        assert_eq!(&expected[5..], "2ce59043b419497855a74c86315e23325e012f86e36d685ed28f037ee899f84d");
+        // version is detected from expected hash
+        assert!(v1.verify(&ab_dir.path().canonicalize().unwrap(), &expected).unwrap());
+
+        let manifest = v2.manifest(&ab_dir.path().canonicalize().unwrap()).unwrap();
+        assert_eq!(manifest.root(), expected);
+        // type tag and little-endian path length precede path and content
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(b"f");
+        hasher.update(&2u64.to_le_bytes());
+        hasher.update(b"abc");
+        assert_eq!(manifest.entries()[1].digest.as_slice(), hasher.finalize().as_bytes());
+        let mut json = Vec::new();
+        manifest.write_json(&mut json).unwrap();
+        assert_eq!(paq::Manifest::read_json(json.as_slice()).unwrap(), manifest);
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn it_hashes_directory_with_metadata() {
+        use std::{
+            fs,
+            os::unix::fs::PermissionsExt,
+            time::{
+                Duration,
+                UNIX_EPOCH,
+            },
+        };
+
+        let file_name = "script";
+        let dir = TempDir::new("it_hashes_directory_with_metadata").unwrap();
+        dir.new_file(file_name, "#!/bin/sh".as_bytes()).unwrap();
+        let file_path = dir.path().join(file_name);
+        let source = dir.path().canonicalize().unwrap();
+        let set_mode = |mode: u32| {
+            fs::set_permissions(&file_path, fs::Permissions::from_mode(mode)).unwrap();
+        };
+        let set_mtime = |nanos: u64| {
+            let file = fs::File::options().write(true).open(&file_path).unwrap();
+            file.set_modified(UNIX_EPOCH + Duration::from_nanos(nanos)).unwrap();
+        };
+
+        let exec = paq::HashOptions::new().mode(paq::ModeBits::Exec);
+        let full = paq::HashOptions::new().mode(paq::ModeBits::Full);
+        set_mode(0o644);
+        let hash_default = paq::HashOptions::new().hash(&source).unwrap();
+        let hash_exec = exec.hash(&source).unwrap();
+        let hash_full = full.hash(&source).unwrap();
+        set_mode(0o755);
+        assert_eq!(paq::HashOptions::new().hash(&source).unwrap(), hash_default);
+        assert_ne!(exec.hash(&source).unwrap(), hash_exec);
+        assert_ne!(full.hash(&source).unwrap(), hash_full);
+        set_mode(0o600);
+        assert_eq!(exec.hash(&source).unwrap(), hash_exec);
+        assert_ne!(full.hash(&source).unwrap(), hash_full);
+
+        let seconds = paq::HashOptions::new()
+            .mtime(Some(paq::TimeGranularity::Seconds));
+        let millis = seconds.clone().mtime(Some(paq::TimeGranularity::Millis));
+        set_mtime(1_000_000_000_100_000_000);
+        let hash_seconds = seconds.manifest(&source).unwrap().entries()[1].digest.clone();
+        let hash_millis = millis.manifest(&source).unwrap().entries()[1].digest.clone();
+        set_mtime(1_000_000_000_900_000_000);
+        assert_eq!(seconds.manifest(&source).unwrap().entries()[1].digest, hash_seconds);
+        assert_ne!(millis.manifest(&source).unwrap().entries()[1].digest, hash_millis);
+
+        let manifest = full.clone().owner_ids(true).owner_names(true).manifest(&source).unwrap();
+        let mut json = Vec::new();
+        manifest.write_json(&mut json).unwrap();
+        let read = paq::Manifest::read_json(json.as_slice()).unwrap();
+        assert_eq!(read.options(), &full.owner_ids(true).owner_names(true));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn it_hashes_directory_with_xattrs() {
+        let file_name = "alpha";
+        let dir = TempDir::new("it_hashes_directory_with_xattrs").unwrap();
+        dir.new_file(file_name, "alpha-body".as_bytes()).unwrap();
+        let file_path = dir.path().join(file_name);
+        let source = dir.path().canonicalize().unwrap();
+        let set_xattr = |name: &str, value: &[u8]| xattr::set(&file_path, name, value).unwrap();
+
+        let xattrs = paq::HashOptions::new().xattrs(true);
+        let included = xattrs.clone().xattr_include(["user.paq"]);
+        let excluded = xattrs.clone().xattr_exclude(["user"]);
+        let hash_default = paq::HashOptions::new().hash(&source).unwrap();
+        let hash_xattrs = xattrs.hash(&source).unwrap();
+        let hash_excluded = excluded.hash(&source).unwrap();
 
-        let file_hash = dir.read_file(hash_file_name).unwrap();
+        set_xattr("user.other", b"1");
+        let hash_included = included.hash(&source).unwrap();
+        assert_ne!(xattrs.hash(&source).unwrap(), hash_xattrs);
+        assert_eq!(excluded.hash(&source).unwrap(), hash_excluded);
+
+        set_xattr("user.paq.label", b"2");
+        assert_ne!(included.hash(&source).unwrap(), hash_included);
+        assert_eq!(excluded.hash(&source).unwrap(), hash_excluded);
+        assert_eq!(paq::HashOptions::new().hash(&source).unwrap(), hash_default);
+
+        // names are sorted, so order attributes were set in does not matter
+        let other_dir = TempDir::new("it_hashes_directory_with_xattrs_reordered").unwrap();
+        other_dir.new_file(file_name, "alpha-body".as_bytes()).unwrap();
+        let other_path = other_dir.path().join(file_name);
+        xattr::set(&other_path, "user.paq.label", b"2").unwrap();
+        xattr::set(&other_path, "user.other", b"1").unwrap();
+        let other_source = other_dir.path().canonicalize().unwrap();
         assert_eq!(
-            file_hash.as_slice(),
-            format!("\"{expectation}\"").as_bytes()
+            xattrs.manifest(&other_source).unwrap().entries()[1].digest,
+            xattrs.manifest(&source).unwrap().entries()[1].digest,
         );
     }
 
     #[test]
-    fn it_outputs_file_hash_using_default_long_arg() {
-        let expectation = "48ec422c86fd2aa1ac182f832c10cf6cb07e4b89d88b83a7794bd8773460072c";
+    fn it_hashes_directory_using_algorithms() {
+        let file_name = "alpha";
+        let file_contents = "alpha-body".as_bytes();
+        let dir = TempDir::new("it_hashes_directory_using_algorithms").unwrap();
+        dir.new_file(file_name, file_contents).unwrap();
+        let source = dir.path().canonicalize().unwrap();
+
+        let file_source = source.join(file_name);
+        let sha256 = paq::HashOptions::new().algorithm(paq::Algorithm::Sha256);
+        // single file root is digest of file digest
+        assert_eq!(
+            sha256.hash(&file_source).unwrap(),
+            "sha256:15765433523517fa4b05e7ed259c3e779a75618644b52ee80112480433f87c34",
+        );
+
+        for algorithm in [
+            paq::Algorithm::Blake3,
+            paq::Algorithm::Sha256,
+            paq::Algorithm::Sha512,
+            paq::Algorithm::Xxh3,
+        ] {
+            let options = paq::HashOptions::new().algorithm(algorithm.clone());
+            let manifest = options.manifest(&source).unwrap();
+            assert_eq!(manifest.entries()[1].content, algorithm.digest(file_contents));
+            assert_eq!(manifest.entries()[1].digest.len(), algorithm.output_len());
+            assert!(manifest.root().starts_with(&algorithm.prefix()));
+            assert_eq!(manifest.root(), options.hash(&source).unwrap());
+
+            let mut json = Vec::new();
+            manifest.write_json(&mut json).unwrap();
+            assert_eq!(paq::Manifest::read_json(json.as_slice()).unwrap(), manifest);
+
+            // algorithm is detected from expected hash
+            assert!(paq::verify_source(&source, manifest.root()).unwrap());
+        }
+    }
 
+    struct Renamed(&'static str, usize);
+
+    impl paq::DigestAlgorithm for Renamed {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn output_len(&self) -> usize {
+            self.1
+        }
+
+        fn hasher(&self) -> Box<dyn paq::DigestHasher> {
+            paq::DigestAlgorithm::hasher(&paq::Algorithm::Sha256)
+        }
+    }
+
+    #[test]
+    fn it_hashes_directory_using_custom_algorithm() {
         let file_name = "alpha";
         let file_contents = "alpha-body".as_bytes();
-        let hash_file_name = "alpha.paq";
-        let dir = TempDir::new("it_outputs_file_hash_using_default_long_arg").unwrap();
+        let dir = TempDir::new("it_hashes_directory_using_custom_algorithm").unwrap();
         dir.new_file(file_name, file_contents).unwrap();
-        let source = dir.path().join(file_name);
+        let source = dir.path().canonicalize().unwrap();
 
-        let mut cmd = Command::new(cargo_bin!("paq"));
-        let assert = cmd
-            .arg(source.as_os_str().to_str().unwrap())
-            .arg("--out")
-            .assert();
-        assert
-            .code(0)
-            .stdout(format!("{expectation}\n"))
-            .success();
+        let algorithm = paq::Algorithm::custom(Renamed("sha256-renamed", 32));
+        let options = paq::HashOptions::new().algorithm(algorithm.clone());
+        let hash = options.hash(&source).unwrap();
+        // same digests as built-in algorithm, named by custom algorithm
+        let sha256 = paq::HashOptions::new().algorithm(paq::Algorithm::Sha256).hash(&source).unwrap();
+        assert_eq!(hash, format!("sha256-renamed:{}", &sha256["sha256:".len()..]));
+
+        let manifest = options.manifest(&source).unwrap();
+        assert_eq!(manifest.root(), hash);
+        assert_eq!(manifest.entries()[1].content, algorithm.digest(file_contents));
+        let mut json = Vec::new();
+        manifest.write_json(&mut json).unwrap();
+        let recorded: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(recorded["options"]["algorithm"], "sha256-renamed");
+
+        // custom algorithm is kept only if expected hash is named by it
+        assert!(options.verify(&source, &hash).unwrap());
+        assert!(options.verify(&source, &sha256).unwrap());
+        assert!(!paq::verify_source(&source, &hash).unwrap());
+        let renamed = paq::Algorithm::custom(Renamed("sha256-renamed", 32));
+        assert!(options.is_comparable(&paq::HashOptions::new().algorithm(renamed)));
+        assert!(!options.is_comparable(&paq::HashOptions::new().algorithm(paq::Algorithm::Sha256)));
+
+        for invalid in [
+            Renamed("", 32),
+            Renamed("sha256", 32),
+            Renamed("blake3-keyed", 32),
+            Renamed("sha:256", 32),
+            Renamed("wide", 65),
+            Renamed("empty", 0),
+        ] {
+            let options = paq::HashOptions::new().algorithm(paq::Algorithm::custom(invalid));
+            let error = options.hash(&source).unwrap_err();
+            assert!(matches!(error, paq::Error::UnsupportedAlgorithm { .. }));
+        }
+        let error = options.clone().key([7u8; 32]).hash(&source).unwrap_err();
+        assert!(matches!(error, paq::Error::UnsupportedKey { .. }));
+    }
+
+    #[test]
+    fn it_hashes_directory_using_key() {
+        let file_name = "alpha";
+        let file_contents = "alpha-body".as_bytes();
+        let dir = TempDir::new("it_hashes_directory_using_key").unwrap();
+        dir.new_file(file_name, file_contents).unwrap();
+        let source = dir.path().canonicalize().unwrap();
+
+        let key = [7u8; 32];
+        let keyed = paq::HashOptions::new().key(key);
+        let hash = keyed.hash(&source).unwrap();
+        assert!(hash.starts_with("blake3-keyed:"));
+        assert_ne!(&hash["blake3-keyed:".len()..], paq::HashOptions::new().hash(&source).unwrap());
+        assert_ne!(hash, paq::HashOptions::new().key([8u8; 32]).hash(&source).unwrap());
+        assert!(!format!("{keyed:?}").contains("7, 7"));
 
-        let file_hash = dir.read_file(hash_file_name).unwrap();
+        let manifest = keyed.manifest(&source).unwrap();
+        assert_eq!(manifest.root(), hash);
         assert_eq!(
-            file_hash.as_slice(),
-            format!("\"{expectation}\"").as_bytes()
+            manifest.entries()[1].content.as_slice(),
+            blake3::keyed_hash(&key, file_contents).as_bytes(),
         );
+
+        // verification requires key
+        assert!(keyed.verify(&source, &hash).unwrap());
+        assert!(!paq::verify_source(&source, &hash).unwrap());
+
+        let mut json = Vec::new();
+        manifest.write_json(&mut json).unwrap();
+        assert!(paq::Manifest::read_json(json.as_slice()).is_err());
+        assert!(paq::Manifest::read_json_keyed(json.as_slice(), [8u8; 32]).is_err());
+        assert_eq!(paq::Manifest::read_json_keyed(json.as_slice(), key).unwrap(), manifest);
+
+        let error = keyed.algorithm(paq::Algorithm::Sha256).hash(&source).unwrap_err();
+        assert!(matches!(error, paq::Error::UnsupportedKey { .. }));
     }
 
     #[test]
-    fn it_outputs_file_hash_using_long_arg() {
-        let expectation = "48ec422c86fd2aa1ac182f832c10cf6cb07e4b89d88b83a7794bd8773460072c";
-
+    fn it_hashes_directory_with_extended_length() {
         let file_name = "alpha";
         let file_contents = "alpha-body".as_bytes();
-        let hash_file_name = "custom.paq";
-        let dir = TempDir::new("it_outputs_file_hash_using_long_arg").unwrap();
+        let dir = TempDir::new("it_hashes_directory_with_extended_length").unwrap();
         dir.new_file(file_name, file_contents).unwrap();
-        let source = dir.path().join(file_name);
-        let mut output = PathBuf::from(&source).parent().unwrap().to_path_buf();
-        output.push(hash_file_name);
+        let source = dir.path().canonicalize().unwrap();
 
-        let mut cmd = Command::new(cargo_bin!("paq"));
-        let assert = cmd
-            .arg(source.as_os_str().to_str().unwrap())
-            .arg(format!("--out={}", output.as_os_str().to_str().unwrap()))
-            .assert();
-        assert
-            .code(0)
-            .stdout(format!("{expectation}\n"))
-            .success();
+        let extended = paq::HashOptions::new().length(100);
+        let root = extended.hash_bytes(&source).unwrap();
+        assert_eq!(root.len(), 100);
+        // extended output starts with default output
+        let hash = paq::HashOptions::new().hash(&source).unwrap();
+        assert_eq!(hash, "7ed5febd35e277763cdfc3e4bee136acf38e48e9462972a732cc4d348a37d653");
+        assert!(extended.hash(&source).unwrap().starts_with(&hash));
+
+        let manifest = extended.manifest(&source).unwrap();
+        assert_eq!(manifest.root().len(), 200);
+        assert_eq!(manifest.entries()[1].digest.len(), 32);
+        let mut json = Vec::new();
+        manifest.write_json(&mut json).unwrap();
+        assert_eq!(paq::Manifest::read_json(json.as_slice()).unwrap(), manifest);
+
+        // length is detected from expected hash
+        assert!(paq::verify_source(&source, manifest.root()).unwrap());
+        assert!(!paq::verify_source(&source, &manifest.root()[..199]).unwrap());
+
+        let error = extended.algorithm(paq::Algorithm::Sha256).hash(&source).unwrap_err();
+        assert!(matches!(error, paq::Error::UnsupportedLength { length: 100, .. }));
+        let error = paq::HashOptions::new().length(0).hash(&source).unwrap_err();
+        assert!(matches!(error, paq::Error::UnsupportedLength { length: 0, .. }));
+        let error = paq::HashOptions::new().length(paq::MAX_OUTPUT_LEN + 1).hash(&source).unwrap_err();
+        assert!(matches!(error, paq::Error::UnsupportedLength { .. }));
+        // overlong expected hash does not match
+        let overlong = "00".repeat(paq::MAX_OUTPUT_LEN + 1);
+        assert!(!paq::verify_source(&source, &overlong).unwrap());
+    }
+
+    #[test]
+    fn it_hashes_directory_as_merkle_tree() {
+        let dir = TempDir::new("it_hashes_directory_as_merkle_tree").unwrap();
+        let alpha = TempDir::new("it_hashes_directory_as_merkle_tree/alpha").unwrap();
+        let bravo = TempDir::new("it_hashes_directory_as_merkle_tree/bravo").unwrap();
+        alpha.new_file("one", "one-body".as_bytes()).unwrap();
+        bravo.new_file("two", "two-body".as_bytes()).unwrap();
+        let source = dir.path().canonicalize().unwrap();
+
+        let options = paq::HashOptions::new();
+        let tree = options.tree(&source).unwrap();
+        // flat root is reported alongside Merkle root
+        assert_eq!(tree.root(), options.hash(&source).unwrap());
+        assert_eq!(tree.merkle_root(), tree.node().to_hex());
+        assert_ne!(tree.merkle_root(), tree.root());
+        let paths: Vec<&Path> = tree.iter().map(|node| node.path()).collect();
+        assert_eq!(
+            paths,
+            ["", "alpha", "alpha/one", "bravo", "bravo/two"].map(Path::new)
+        );
+        let file = tree.get(Path::new("alpha/one")).unwrap();
+        assert_eq!(file.digest(), &file.entry().digest);
+        assert!(file.children().is_empty());
+        assert!(tree.get(Path::new("charlie")).is_none());
+
+        // change only affects digests of ancestors
+        bravo.new_file("two", "changed-body".as_bytes()).unwrap();
+        let changed = options.tree(&source).unwrap();
+        let digest = |tree: &paq::Tree, path: &str| tree.get(Path::new(path)).unwrap().digest().clone();
+        assert_eq!(digest(&changed, "alpha"), digest(&tree, "alpha"));
+        assert_ne!(digest(&changed, "bravo"), digest(&tree, "bravo"));
+        assert_ne!(changed.merkle_root(), tree.merkle_root());
+
+        // tree of saved manifest matches tree of source
+        let manifest = options.manifest(&source).unwrap();
+        assert_eq!(paq::Tree::from_manifest(&manifest), changed);
+
+        // entries without parent directory in manifest are attached to nearest ancestor by relative path
+        let mut json = Vec::new();
+        manifest.write_json(&mut json).unwrap();
+        let mut value: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        value["entries"].as_array_mut().unwrap().retain(|entry| entry["path"] != "bravo");
+        let mut digests: Vec<Vec<u8>> = value["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| {
+                let hex = entry["digest"].as_str().unwrap();
+                (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+            })
+            .collect();
+        digests.sort();
+        value["root"] = blake3::hash(&digests.concat()).to_hex().as_str().into();
+        let orphaned = paq::Manifest::read_json(value.to_string().as_bytes()).unwrap();
+        let tree = paq::Tree::from_manifest(&orphaned);
+        let children: Vec<&Path> = tree.node().children().iter().map(|node| node.path()).collect();
+        assert_eq!(children, ["alpha", "bravo/two"].map(Path::new));
+        assert_eq!(tree.get(Path::new("bravo/two")).unwrap().digest(), &digest(&changed, "bravo/two"));
+        assert!(tree.get(Path::new("two")).is_none());
+    }
+
+    #[test]
+    fn it_hashes_directory_using_cache() {
+        let dir = TempDir::new("it_hashes_directory_using_cache/source").unwrap();
+        dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        dir.new_file("bravo", "bravo-body".as_bytes()).unwrap();
+        let source = dir.path().canonicalize().unwrap();
+        let cache_dir = TempDir::new("it_hashes_directory_using_cache/cache").unwrap();
+        let cache = cache_dir.path().join("cache.json");
+        let read_cache = || -> serde_json::Value { serde_json::from_slice(&fs::read(&cache).unwrap()).unwrap() };
+        let options = paq::HashOptions::new().cache(Some(cache.clone()));
+        let expectation = paq::HashOptions::new().hash(&source).unwrap();
 
-        let file_hash = dir.read_file(hash_file_name).unwrap();
+        // files changed within a second of hashing are not cached
+        assert_eq!(options.hash(&source).unwrap(), expectation);
+        assert_eq!(read_cache()["entries"].as_array().unwrap().len(), 0);
+        thread::sleep(Duration::from_millis(1100));
+        assert_eq!(options.hash(&source).unwrap(), expectation);
+        let cached = read_cache();
+        assert_eq!(cached["entries"].as_array().unwrap().len(), 2);
+        assert_eq!(cached["entries"][0]["path"], "alpha");
+
+        // content digests are only computed (and cached) for manifests
+        assert!(cached["entries"][0]["content"].is_null());
+        assert_eq!(options.manifest(&source).unwrap().root(), expectation);
+        let mut cached = read_cache();
+        assert!(cached["entries"][0]["content"].is_string());
+
+        // cached digests of unchanged files are used without reading files
+        cached["entries"][0]["digest"] = cached["entries"][1]["digest"].clone();
+        fs::write(&cache, cached.to_string()).unwrap();
+        assert_ne!(options.hash(&source).unwrap(), expectation);
+        let manifest = options.manifest(&source).unwrap();
+        assert_eq!(manifest.entries()[1].digest, manifest.entries()[2].digest);
+        assert_eq!(manifest.options(), &paq::HashOptions::new());
+
+        // changed files are hashed again
+        dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        assert_eq!(options.hash(&source).unwrap(), expectation);
+
+        // cache of other algorithm is not used
+        fs::write(&cache, cached.to_string()).unwrap();
+        let sha256 = options.clone().algorithm(paq::Algorithm::Sha256);
         assert_eq!(
-            file_hash.as_slice(),
-            format!("\"{expectation}\"").as_bytes()
+            sha256.hash(&source).unwrap(),
+            paq::HashOptions::new().algorithm(paq::Algorithm::Sha256).hash(&source).unwrap()
         );
+        assert_eq!(read_cache()["options"]["algorithm"], "sha256");
+        assert_eq!(options.hash(&source).unwrap(), expectation);
+    }
+
+    #[test]
+    fn it_watches_directory_for_changes() {
+        let dir = TempDir::new("it_watches_directory_for_changes").unwrap();
+        dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        let source = dir.path().canonicalize().unwrap();
+        let options = paq::HashOptions::new();
+        let events = options.watch_channel(&source, Duration::from_millis(100));
+        let timeout = Duration::from_secs(10);
+
+        let event = events.recv_timeout(timeout).unwrap().unwrap();
+        assert_eq!(event.root(), options.hash(&source).unwrap());
+        assert!(event.changed().is_empty());
+
+        dir.new_file("bravo", "bravo-body".as_bytes()).unwrap();
+        let event = events.recv_timeout(timeout).unwrap().unwrap();
+        assert_eq!(event.root(), options.hash(&source).unwrap());
+        assert!(event.changed().contains(&PathBuf::from("bravo")));
+        assert_eq!(event.manifest().entries().len(), 3);
+
+        let subdir = TempDir::new("it_watches_directory_for_changes/charlie").unwrap();
+        subdir.new_file("delta", "delta-body".as_bytes()).unwrap();
+        fs::remove_file(source.join("alpha")).unwrap();
+        // events may be split across debounce windows, so wait for settled hash
+        let expectation = options.hash(&source).unwrap();
+        let settled = iter::from_fn(|| events.recv_timeout(timeout).ok())
+            .map(|event| event.unwrap())
+            .find(|event| event.root() == expectation)
+            .unwrap();
+        assert!(settled.manifest().entries().iter().all(|entry| entry.path != Path::new("alpha")));
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn it_watches_relative_directory_for_content_changes() {
+        let dir = TempDir::new("it_watches_relative_directory_for_content_changes").unwrap();
+        dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        let source = dir.path().canonicalize().unwrap();
+        // relative to working directory of tests, or to root (parent of root is root)
+        let relative: PathBuf = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .components()
+            .skip(1)
+            .map(|_| Path::new(".."))
+            .chain(iter::once(source.strip_prefix("/").unwrap()))
+            .collect();
+        let options = paq::HashOptions::new();
+        let events = options.watch_channel(&relative, Duration::from_millis(100));
+        let timeout = Duration::from_secs(10);
+
+        let event = events.recv_timeout(timeout).unwrap().unwrap();
+        assert_eq!(event.root(), options.hash(&source).unwrap());
+
+        dir.new_file("alpha", "changed-body".as_bytes()).unwrap();
+        let expectation = options.hash(&source).unwrap();
+        assert_ne!(expectation, event.root());
+        let settled = iter::from_fn(|| events.recv_timeout(timeout).ok())
+            .map(|event| event.unwrap())
+            .find(|event| event.root() == expectation)
+            .unwrap();
+        assert!(settled.changed().contains(&PathBuf::from("alpha")));
+    }
+
+    #[test]
+    fn it_watches_nested_directories_and_ignore_files_for_changes() {
+        let dir = TempDir::new("it_watches_nested_directories_and_ignore_files_for_changes").unwrap();
+        // directory is renamed, so is removed along with source
+        fs::create_dir(dir.path().join("alpha")).unwrap();
+        dir.new_file("alpha/bravo", "bravo-body".as_bytes()).unwrap();
+        dir.new_file("alpha/charlie", "charlie-body".as_bytes()).unwrap();
+        let source = dir.path().canonicalize().unwrap();
+        let options = paq::HashOptions::new().ignore_files(true);
+        let events = options.watch_channel(&source, Duration::from_millis(100));
+        let timeout = Duration::from_secs(10);
+        // events may be split across debounce windows, so wait for settled hash
+        let settled = |expectation: String| {
+            iter::from_fn(|| events.recv_timeout(timeout).ok())
+                .map(|event| event.unwrap())
+                .find(|event| event.root() == expectation)
+                .unwrap()
+        };
+
+        let event = events.recv_timeout(timeout).unwrap().unwrap();
+        assert_eq!(event.root(), options.hash(&source).unwrap());
+
+        // ignore file filters entries that did not change
+        dir.new_file("alpha/.paqignore", "charlie\n".as_bytes()).unwrap();
+        let event = settled(options.hash(&source).unwrap());
+        assert!(event.manifest().entries().iter().all(|entry| entry.path != Path::new("alpha/charlie")));
+
+        // nested file is hashed again
+        dir.new_file("alpha/bravo", "changed-body".as_bytes()).unwrap();
+        settled(options.hash(&source).unwrap());
+
+        // renamed directory is removed along with its entries
+        fs::rename(source.join("alpha"), source.join("delta")).unwrap();
+        let event = settled(options.hash(&source).unwrap());
+        assert!(event.manifest().entries().iter().all(|entry| !entry.path.starts_with("alpha")));
+        assert!(event.manifest().entries().iter().any(|entry| entry.path == Path::new("delta/bravo")));
+    }
+
+    #[test]
+    fn it_watches_directory_metadata_for_changes() {
+        let dir = TempDir::new("it_watches_directory_metadata_for_changes").unwrap();
+        let subdir = TempDir::new("it_watches_directory_metadata_for_changes/delta").unwrap();
+        subdir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        let source = dir.path().canonicalize().unwrap();
+        let options = paq::HashOptions::new().mtime(Some(paq::TimeGranularity::Nanos));
+        let events = options.watch_channel(&source, Duration::from_millis(100));
+        let timeout = Duration::from_secs(10);
+
+        let event = events.recv_timeout(timeout).unwrap().unwrap();
+        assert_eq!(event.root(), options.hash(&source).unwrap());
+
+        // only new file is reported, though modification time of its directory changes too
+        subdir.new_file("bravo", "bravo-body".as_bytes()).unwrap();
+        let expectation = options.hash(&source).unwrap();
+        assert_ne!(expectation, event.root());
+        assert!(iter::from_fn(|| events.recv_timeout(timeout).ok())
+            .map(|event| event.unwrap())
+            .any(|event| event.root() == expectation));
+    }
+
+    #[test]
+    fn it_hashes_archives_as_if_extracted() {
+        use std::io::Write;
+
+        let dir = TempDir::new("it_hashes_archives_as_if_extracted/source").unwrap();
+        let subdir = TempDir::new("it_hashes_archives_as_if_extracted/source/bravo").unwrap();
+        dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        subdir.new_file("charlie", "charlie-body".as_bytes()).unwrap();
+        dir.new_symlink("delta", PathBuf::from("alpha")).unwrap();
+        let source = dir.path().canonicalize().unwrap();
+        let archives = TempDir::new("it_hashes_archives_as_if_extracted/archives").unwrap();
+
+        let mut builder = tar::Builder::new(Vec::new());
+        builder.follow_symlinks(false);
+        builder.append_dir_all(".", &source).unwrap();
+        let tar = builder.into_inner().unwrap();
+        fs::write(archives.path().join("source.tar"), &tar).unwrap();
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&tar).unwrap();
+        fs::write(archives.path().join("source.tar.gz"), encoder.finish().unwrap()).unwrap();
+        fs::write(archives.path().join("source.tar.zst"), zstd::encode_all(tar.as_slice(), 0).unwrap()).unwrap();
+
+        // zip without directory entries, which are added as parents
+        let mut zip = zip::ZipWriter::new(fs::File::create(archives.path().join("source.zip")).unwrap());
+        let zip_options = zip::write::SimpleFileOptions::default();
+        zip.start_file("alpha", zip_options).unwrap();
+        zip.write_all(b"alpha-body").unwrap();
+        zip.start_file("./bravo/charlie", zip_options).unwrap();
+        zip.write_all(b"charlie-body").unwrap();
+        zip.add_symlink("delta", "alpha", zip_options).unwrap();
+        zip.finish().unwrap();
+
+        let options = paq::HashOptions::new().archive(true);
+        let expectation = paq::HashOptions::new().hash(&source).unwrap();
+        for name in ["source.tar", "source.tar.gz", "source.tar.zst", "source.zip"] {
+            let archive = archives.path().join(name);
+            assert_eq!(options.hash(&archive).unwrap(), expectation, "{name}");
+        }
+        // directory sources are hashed as usual
+        assert_eq!(options.hash(&source).unwrap(), expectation);
+        let manifest = options.manifest(&archives.path().join("source.zip")).unwrap();
+        assert_eq!(manifest.entries().len(), 5);
+        assert_eq!(manifest.options(), &options);
+
+        let filtered = options.clone().ignore_hidden(true).exclude(["bravo"]);
+        assert_eq!(
+            filtered.hash(&archives.path().join("source.tar")).unwrap(),
+            filtered.hash(&source).unwrap()
+        );
+        let error = options.clone().ignore_files(true).hash(&archives.path().join("source.tar")).unwrap_err();
+        assert!(matches!(error, paq::Error::Archive { .. }));
+        assert_eq!(error.to_string(), "failed to hash archive: ignore files are not supported");
+    }
+
+    #[test]
+    fn it_fails_hashing_archive_with_unsafe_entries() {
+        let dir = TempDir::new("it_fails_hashing_archive_with_unsafe_entries").unwrap();
+        let options = paq::HashOptions::new().archive(true);
+        let cases: [(&[&str], &str); 4] = [
+            (&["alpha", "./alpha"], "duplicate entry"),
+            (&["/alpha"], "absolute path"),
+            (&["alpha/../../bravo"], "path contains `..` component"),
+            (&["alpha", "alpha/bravo"], "parent is not a directory"),
+        ];
+        for (index, (names, reason)) in cases.into_iter().enumerate() {
+            let mut builder = tar::Builder::new(Vec::new());
+            for name in names {
+                // raw header name, as builder rejects unsafe paths
+                let mut header = tar::Header::new_gnu();
+                header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+                header.set_size(4);
+                header.set_cksum();
+                builder.append(&header, "body".as_bytes()).unwrap();
+            }
+            let archive = dir.path().join(format!("{index}.tar"));
+            fs::write(&archive, builder.into_inner().unwrap()).unwrap();
+
+            let error = options.hash(&archive).unwrap_err();
+            assert!(
+                matches!(&error, paq::Error::Archive { reason: actual, .. } if actual == reason),
+                "{error}"
+            );
+        }
+        dir.new_file("text", "not an archive".as_bytes()).unwrap();
+        let error = options.hash(&dir.path().join("text")).unwrap_err();
+        assert!(matches!(error, paq::Error::Archive { .. }));
+    }
+
+    #[test]
+    fn it_hashes_tar_stream_as_if_extracted() {
+        let dir = TempDir::new("it_hashes_tar_stream_as_if_extracted").unwrap();
+        let subdir = TempDir::new("it_hashes_tar_stream_as_if_extracted/bravo").unwrap();
+        dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        subdir.new_file("charlie", "charlie-body".as_bytes()).unwrap();
+        let source = dir.path().canonicalize().unwrap();
+        let mut builder = tar::Builder::new(Vec::new());
+        builder.append_dir_all(".", &source).unwrap();
+        let tar = builder.into_inner().unwrap();
+
+        let options = paq::HashOptions::new();
+        let expectation = options.hash(&source).unwrap();
+        assert_eq!(options.hash_tar(tar.as_slice()).unwrap(), expectation);
+        let compressed = zstd::encode_all(tar.as_slice(), 0).unwrap();
+        assert_eq!(options.hash_tar(compressed.as_slice()).unwrap(), expectation);
+        let manifest = options.manifest_tar(tar.as_slice()).unwrap();
+        assert_eq!(manifest.root(), expectation);
+        assert_eq!(manifest.entries().len(), 4);
+
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        zip.start_file("alpha", zip::write::SimpleFileOptions::default()).unwrap();
+        let zip = zip.finish().unwrap().into_inner();
+        let error = options.hash_tar(zip.as_slice()).unwrap_err();
+        assert_eq!(error.to_string(), "failed to hash archive: zip archives cannot be read from stream");
+    }
+
+    #[test]
+    fn it_reports_and_lists_checksums() {
+        let dir = TempDir::new("it_reports_and_lists_checksums").unwrap();
+        dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        dir.new_file("back\\slash", "bravo-body".as_bytes()).unwrap();
+        dir.new_symlink("charlie", PathBuf::from("alpha")).unwrap();
+        let source = dir.path().canonicalize().unwrap();
+        let options = paq::HashOptions::new().algorithm(paq::Algorithm::Sha256);
+        let manifest = options.manifest(&source).unwrap();
+
+        let report = paq::Report::from_manifest(&manifest);
+        assert_eq!(report.root(), manifest.root());
+        assert_eq!(report.entry_count(), 4);
+        assert_eq!(report.total_bytes(), 25);
+        // reports are summarized without content digests of manifest
+        assert_eq!(options.report(&source).unwrap(), report);
+        let mut json = Vec::new();
+        report.write_json(&mut json).unwrap();
+        assert_eq!(paq::Report::read_json(json.as_slice()).unwrap(), report);
+        let value: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(value["version"], paq::REPORT_VERSION);
+        assert_eq!(value["algorithm"], "sha256");
+        assert_eq!(value["hash_version"], 1);
+
+        // symlinks and directories are not listed, as checksum tools follow or reject them
+        let mut checksums = Vec::new();
+        manifest.write_checksums(&mut checksums, &source).unwrap();
+        let alpha = paq::Algorithm::Sha256.digest(b"alpha-body");
+        let bravo = paq::Algorithm::Sha256.digest(b"bravo-body");
+        assert_eq!(
+            String::from_utf8(checksums).unwrap(),
+            format!(
+                "{}  alpha\n\\{}  back\\\\slash\n",
+                alpha.iter().map(|byte| format!("{byte:02x}")).collect::<String>(),
+                bravo.iter().map(|byte| format!("{byte:02x}")).collect::<String>()
+            )
+        );
+    }
+
+    #[test]
+    fn it_combines_labeled_source_hashes() {
+        let dir = TempDir::new("it_combines_labeled_source_hashes").unwrap();
+        dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        dir.new_file("bravo", "bravo-body".as_bytes()).unwrap();
+        let options = paq::HashOptions::new();
+        let alpha = options.hash(&dir.path().join("alpha")).unwrap();
+        let bravo = options.hash(&dir.path().join("bravo")).unwrap();
+
+        let combined = options.combine([("alpha", &alpha), ("bravo", &bravo)]);
+        assert_eq!(combined, options.combine([("bravo", &bravo), ("alpha", &alpha)]));
+        // hashes are bound to labels
+        assert_ne!(combined, options.combine([("alpha", &bravo), ("bravo", &alpha)]));
+        assert_ne!(combined, options.combine([("alpha", &alpha)]));
+        let sha256 = paq::HashOptions::new().algorithm(paq::Algorithm::Sha256);
+        assert!(sha256.combine([("alpha", &alpha)]).starts_with("sha256:"));
+    }
+
+    #[test]
+    fn it_reports_progress_of_hashed_entries() {
+        use std::sync::{
+            atomic::{
+                AtomicU64,
+                Ordering,
+            },
+            Arc,
+        };
+
+        let dir = TempDir::new("it_reports_progress_of_hashed_entries").unwrap();
+        let subdir = TempDir::new("it_reports_progress_of_hashed_entries/bravo").unwrap();
+        dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        subdir.new_file("charlie", "charlie-body".as_bytes()).unwrap();
+        dir.new_symlink("delta", PathBuf::from("alpha")).unwrap();
+        let source = dir.path().canonicalize().unwrap();
+
+        let files = Arc::new(AtomicU64::new(0));
+        let bytes = Arc::new(AtomicU64::new(0));
+        let (file_counter, byte_counter) = (files.clone(), bytes.clone());
+        let options = paq::HashOptions::new().progress(Some(Arc::new(move |kind, len| {
+            if kind == paq::EntryKind::File {
+                file_counter.fetch_add(1, Ordering::Relaxed);
+            }
+            byte_counter.fetch_add(len, Ordering::Relaxed);
+        })));
+        let totals = options.scan(&source).unwrap();
+        assert_eq!(totals, paq::ScanTotals { files: 2, bytes: 27 });
+
+        assert_eq!(options.hash(&source).unwrap(), paq::HashOptions::new().hash(&source).unwrap());
+        assert_eq!(files.load(Ordering::Relaxed), totals.files);
+        assert_eq!(bytes.load(Ordering::Relaxed), totals.bytes);
+        // progress receiver is not recorded
+        let manifest = options.manifest(&source).unwrap();
+        assert_eq!(manifest.options(), &paq::HashOptions::new());
+        assert_eq!(files.load(Ordering::Relaxed), 2 * totals.files);
+    }
+
+    #[test]
+    fn it_hashes_using_thread_pool_or_sequentially() {
+        use std::sync::{
+            Arc,
+            Mutex,
+        };
+
+        let dir = TempDir::new("it_hashes_using_thread_pool_or_sequentially").unwrap();
+        for index in 0..20 {
+            dir.new_file(&format!("file-{index}"), format!("body-{index}").as_bytes()).unwrap();
+        }
+        let source = dir.path().canonicalize().unwrap();
+        let expectation = paq::HashOptions::new().manifest(&source).unwrap();
+
+        // progress receiver records threads hashing entries
+        let threads = Arc::new(Mutex::new(Vec::new()));
+        let recorder = threads.clone();
+        let progress: Arc<dyn paq::Progress> = Arc::new(move |_kind, _len| {
+            recorder.lock().unwrap().push(thread::current().id());
+        });
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+        let pool_threads: Vec<thread::ThreadId> = pool.broadcast(|_| thread::current().id());
+        let pooled = paq::HashOptions::new()
+            .parallelism(paq::Parallelism::Pool(Arc::new(pool)))
+            .progress(Some(progress.clone()));
+        // manifests hashing alike are equal regardless of thread pool or progress receiver
+        assert_eq!(pooled.manifest(&source).unwrap(), expectation);
+        assert!(threads.lock().unwrap().iter().all(|id| pool_threads.contains(id)));
+        assert_eq!(pooled, paq::HashOptions::new());
+        assert_ne!(pooled, paq::HashOptions::new().ignore_hidden(true));
+
+        threads.lock().unwrap().clear();
+        let sequential = paq::HashOptions::new()
+            .parallelism(paq::Parallelism::Sequential)
+            .progress(Some(progress));
+        assert_eq!(sequential.hash(&source).unwrap(), expectation.root());
+        assert!(threads.lock().unwrap().iter().all(|id| *id == thread::current().id()));
+        assert_eq!(threads.lock().unwrap().len(), 21);
+    }
+
+    #[test]
+    fn it_diffs_manifests() {
+        let old_dir = TempDir::new("it_diffs_manifests/old").unwrap();
+        old_dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        old_dir.new_file("bravo", "bravo-body".as_bytes()).unwrap();
+        old_dir.new_file("charlie", "charlie-body".as_bytes()).unwrap();
+        old_dir.new_file("delta", "delta-body".as_bytes()).unwrap();
+        let new_dir = TempDir::new("it_diffs_manifests/new").unwrap();
+        new_dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        new_dir.new_file("bravo", "bravo-changed".as_bytes()).unwrap();
+        new_dir.new_file("echo", "charlie-body".as_bytes()).unwrap();
+        new_dir.new_file("foxtrot", "foxtrot-body".as_bytes()).unwrap();
+
+        let old = paq::hash_source_with_manifest(&old_dir.path().canonicalize().unwrap(), false).unwrap();
+        let new = paq::hash_source_with_manifest(&new_dir.path().canonicalize().unwrap(), false).unwrap();
+        let diff = paq::Diff::new(&old, &new);
+
+        let changes: Vec<String> = diff.changes().iter().map(|change| change.to_string()).collect();
+        assert_eq!(changes, vec!["M bravo", "D delta", "R charlie -> echo", "A foxtrot"]);
+        assert!(paq::Diff::new(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn it_hashes_directory_respecting_ignore_files() {
+        let dir = TempDir::new("it_hashes_directory_respecting_ignore_files").unwrap();
+        dir.new_file(".gitignore", "target/\n*.log\n".as_bytes()).unwrap();
+        dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        dir.new_file("alpha.log", "alpha-log".as_bytes()).unwrap();
+        let target_dir = TempDir::new("it_hashes_directory_respecting_ignore_files/target").unwrap();
+        target_dir.new_file("bravo", "bravo-body".as_bytes()).unwrap();
+        let nested_dir = TempDir::new("it_hashes_directory_respecting_ignore_files/nested").unwrap();
+        nested_dir.new_file(".paqignore", "!charlie.log\ndelta\n".as_bytes()).unwrap();
+        nested_dir.new_file("charlie.log", "charlie-log".as_bytes()).unwrap();
+        nested_dir.new_file("delta", "delta-body".as_bytes()).unwrap();
+        let source = dir.path().canonicalize().unwrap();
+
+        let manifest = paq::HashOptions::new()
+            .ignore_files(true)
+            .manifest(&source)
+            .unwrap();
+        let paths: Vec<&str> = manifest.entries()
+            .iter()
+            .map(|entry| entry.path.to_str().unwrap())
+            .collect();
+        assert_eq!(paths, vec!["", ".gitignore", "alpha", "nested", "nested/.paqignore", "nested/charlie.log"]);
+        assert_eq!(manifest.options(), &paq::HashOptions::new().ignore_files(true));
+
+        let hash_not_ignored = paq::HashOptions::new().hash(&source).unwrap();
+        assert_ne!(manifest.root(), hash_not_ignored.as_str());
+    }
+
+    #[test]
+    fn it_hashes_directory_with_include_and_exclude_patterns() {
+        let dir = TempDir::new("it_hashes_directory_with_include_and_exclude_patterns").unwrap();
+        dir.new_file("alpha.rs", "alpha-body".as_bytes()).unwrap();
+        dir.new_file("bravo.txt", "bravo-body".as_bytes()).unwrap();
+        let nested_dir = TempDir::new("it_hashes_directory_with_include_and_exclude_patterns/nested").unwrap();
+        nested_dir.new_file("charlie.rs", "charlie-body".as_bytes()).unwrap();
+        let fixtures_dir = TempDir::new("it_hashes_directory_with_include_and_exclude_patterns/fixtures").unwrap();
+        fixtures_dir.new_file("delta.rs", "delta-body".as_bytes()).unwrap();
+        let source = dir.path().canonicalize().unwrap();
+
+        let manifest = paq::HashOptions::new()
+            .include(["*.rs"])
+            .exclude(["fixtures"])
+            .manifest(&source)
+            .unwrap();
+        let paths: Vec<&str> = manifest.entries()
+            .iter()
+            .map(|entry| entry.path.to_str().unwrap())
+            .collect();
+        assert_eq!(paths, vec!["", "alpha.rs", "nested", "nested/charlie.rs"]);
+
+        let manifest = paq::HashOptions::new()
+            .exclude(["nested/*.rs", "/alpha.rs"])
+            .manifest(&source)
+            .unwrap();
+        let paths: Vec<&str> = manifest.entries()
+            .iter()
+            .map(|entry| entry.path.to_str().unwrap())
+            .collect();
+        assert_eq!(paths, vec!["", "bravo.txt", "fixtures", "fixtures/delta.rs", "nested"]);
+
+        // contents pattern excludes directory itself, so source hashes as without directory
+        let hash_excluded = paq::HashOptions::new()
+            .exclude(["fixtures/**"])
+            .hash(&source)
+            .unwrap();
+        let hash_without = paq::HashOptions::new()
+            .exclude(["fixtures"])
+            .hash(&source)
+            .unwrap();
+        assert_eq!(hash_excluded, hash_without);
+        let manifest = paq::HashOptions::new()
+            .exclude(["**/nested/**"])
+            .manifest(&source)
+            .unwrap();
+        assert!(manifest.entries().iter().all(|entry| !entry.path.starts_with("nested")));
+
+        let error = paq::HashOptions::new()
+            .exclude(["["])
+            .hash(&source)
+            .unwrap_err();
+        assert!(matches!(error, paq::Error::Pattern { .. }));
+    }
+
+    #[test]
+    fn it_returns_walk_error_for_missing_source() {
+        let dir = TempDir::new("it_returns_walk_error_for_missing_source").unwrap();
+        let source = dir.path().join("missing");
+
+        let error = paq::try_hash_source(&source, false).unwrap_err();
+        assert!(matches!(error, paq::Error::Walk { .. }));
+        assert_eq!(error.path(), PathBuf::from("").as_path());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn it_hashes_file_names_not_valid_utf8() {
+        use std::{
+            ffi::OsStr,
+            os::unix::ffi::OsStrExt,
+        };
+
+        let file_name = OsStr::from_bytes(b"invalid-\xff%");
+        let dir = TempDir::new("it_hashes_file_names_not_valid_utf8").unwrap();
+        std::fs::write(dir.path().join(file_name), "body").unwrap();
+        let source = dir.path().canonicalize().unwrap();
+
+        let manifest = paq::hash_source_with_manifest(&source, false).unwrap();
+        assert_eq!(manifest.entries()[1].path, PathBuf::from(file_name));
+
+        let mut json = Vec::new();
+        manifest.write_json(&mut json).unwrap();
+        assert!(String::from_utf8(json.clone()).unwrap().contains("\"invalid-%FF%25\""));
+        assert_eq!(paq::Manifest::read_json(json.as_slice()).unwrap(), manifest);
+
+        // raw path bytes are hashed
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(b"invalid-\xff%");
+        hasher.update(b"body");
+        assert_eq!(manifest.entries()[1].digest.as_slice(), hasher.finalize().as_bytes());
+    }
+}
+
+// added allow deprecated attribute due to cargo_bin notice without a resolution
+#[allow(deprecated)]
+mod bin {
+    use crate::utils::TempDir;
+    use assert_cmd::{
+        cargo::cargo_bin,
+        Command,
+    };
+    use std::path::{
+        Path,
+        PathBuf,
+    };
+
+    #[test]
+    fn it_outputs_file_hash_using_default_short_arg() {
+        let expectation = "48ec422c86fd2aa1ac182f832c10cf6cb07e4b89d88b83a7794bd8773460072c";
+
+        let file_name = "alpha";
+        let file_contents = "alpha-body".as_bytes();
+        let hash_file_name = "alpha.paq";
+        let dir = TempDir::new("it_outputs_file_hash_using_default_short_arg").unwrap();
+        dir.new_file(file_name, file_contents).unwrap();
+        let source = dir.path().join(file_name);
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        let assert = cmd
+            .arg(source.as_os_str().to_str().unwrap())
+            .arg("-o")
+            .assert();
+        assert
+            .code(0)
+            .stdout(format!("{expectation}\n"))
+            .success();
+
+        let report = paq::Report::read_json(dir.read_file(hash_file_name).unwrap().as_slice()).unwrap();
+        assert_eq!(report.root(), expectation);
+    }
+
+    #[test]
+    fn it_outputs_file_hash_using_short_arg() {
+        let expectation = "48ec422c86fd2aa1ac182f832c10cf6cb07e4b89d88b83a7794bd8773460072c";
+
+        let file_name = "alpha";
+        let file_contents = "alpha-body".as_bytes();
+        let hash_file_name = "custom.paq";
+        let dir = TempDir::new("it_outputs_file_hash_using_short_arg").unwrap();
+        dir.new_file(file_name, file_contents).unwrap();
+        let source = dir.path().join(file_name);
+        let mut output = PathBuf::from(&source).parent().unwrap().to_path_buf();
+        output.push(hash_file_name);
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        let assert = cmd
+            .arg(source.as_os_str().to_str().unwrap())
+            .arg(format!("-o={}", output.as_os_str().to_str().unwrap()))
+            .assert();
+        assert
+            .code(0)
+            .stdout(format!("{expectation}\n"))
+            .success();
+
+        let report = paq::Report::read_json(dir.read_file(hash_file_name).unwrap().as_slice()).unwrap();
+        assert_eq!(report.root(), expectation);
+    }
+
+    #[test]
+    fn it_outputs_file_hash_using_default_long_arg() {
+        let expectation = "48ec422c86fd2aa1ac182f832c10cf6cb07e4b89d88b83a7794bd8773460072c";
+
+        let file_name = "alpha";
+        let file_contents = "alpha-body".as_bytes();
+        let hash_file_name = "alpha.paq";
+        let dir = TempDir::new("it_outputs_file_hash_using_default_long_arg").unwrap();
+        dir.new_file(file_name, file_contents).unwrap();
+        let source = dir.path().join(file_name);
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        let assert = cmd
+            .arg(source.as_os_str().to_str().unwrap())
+            .arg("--out")
+            .assert();
+        assert
+            .code(0)
+            .stdout(format!("{expectation}\n"))
+            .success();
+
+        let report = paq::Report::read_json(dir.read_file(hash_file_name).unwrap().as_slice()).unwrap();
+        assert_eq!(report.root(), expectation);
+    }
+
+    #[test]
+    fn it_outputs_file_hash_using_long_arg() {
+        let expectation = "48ec422c86fd2aa1ac182f832c10cf6cb07e4b89d88b83a7794bd8773460072c";
+
+        let file_name = "alpha";
+        let file_contents = "alpha-body".as_bytes();
+        let hash_file_name = "custom.paq";
+        let dir = TempDir::new("it_outputs_file_hash_using_long_arg").unwrap();
+        dir.new_file(file_name, file_contents).unwrap();
+        let source = dir.path().join(file_name);
+        let mut output = PathBuf::from(&source).parent().unwrap().to_path_buf();
+        output.push(hash_file_name);
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        let assert = cmd
+            .arg(source.as_os_str().to_str().unwrap())
+            .arg(format!("--out={}", output.as_os_str().to_str().unwrap()))
+            .assert();
+        assert
+            .code(0)
+            .stdout(format!("{expectation}\n"))
+            .success();
+
+        let report = paq::Report::read_json(dir.read_file(hash_file_name).unwrap().as_slice()).unwrap();
+        assert_eq!(report.root(), expectation);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn it_hashes_file_names_not_valid_utf8() {
+        use std::{
+            ffi::OsStr,
+            os::unix::ffi::OsStrExt,
+        };
+
+        let file_name = OsStr::from_bytes(b"invalid-\xff");
+        let dir = TempDir::new("bin_it_hashes_file_names_not_valid_utf8").unwrap();
+        std::fs::write(dir.path().join(file_name), "body").unwrap();
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        let assert = cmd
+            .arg(dir.path().as_os_str())
+            .arg("--manifest")
+            .assert();
+        let output = assert
+            .code(0)
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+
+        let manifest = paq::Manifest::read_json(output.as_slice()).unwrap();
+        assert_eq!(manifest.entries()[1].path, PathBuf::from(file_name));
+        assert!(String::from_utf8(output).unwrap().contains("\"path\": \"invalid-%FF\""));
+    }
+
+    #[test]
+    fn it_outputs_manifest_to_stdout() {
+        let expectation = "48ec422c86fd2aa1ac182f832c10cf6cb07e4b89d88b83a7794bd8773460072c";
+
+        let file_name = "alpha";
+        let file_contents = "alpha-body".as_bytes();
+        let dir = TempDir::new("it_outputs_manifest_to_stdout").unwrap();
+        dir.new_file(file_name, file_contents).unwrap();
+        let source = dir.path().join(file_name);
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        let assert = cmd
+            .arg(source.as_os_str().to_str().unwrap())
+            .arg("--manifest")
+            .assert();
+        let output = assert
+            .code(0)
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+
+        let manifest = paq::Manifest::read_json(output.as_slice()).unwrap();
+        assert_eq!(manifest.root(), expectation);
+        assert_eq!(manifest.entries().len(), 1);
+    }
+
+    #[test]
+    fn it_outputs_report_and_checksums_by_format() {
+        let dir = TempDir::new("it_outputs_report_and_checksums_by_format").unwrap();
+        let source_dir = TempDir::new("it_outputs_report_and_checksums_by_format/source").unwrap();
+        source_dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        let options = paq::HashOptions::new().algorithm(paq::Algorithm::Sha256);
+        let expectation = options.hash(source_dir.path()).unwrap();
+        let hash_file = dir.path().join("source.paq");
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        let output = cmd
+            .arg(source_dir.path().as_os_str())
+            .arg("--algorithm=sha256")
+            .arg("--format=json")
+            .arg(format!("--out={}", hash_file.display()))
+            .assert()
+            .code(0)
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let report = paq::Report::read_json(output.as_slice()).unwrap();
+        assert_eq!(report.root(), expectation);
+        assert_eq!(report.options(), &options);
+        assert_eq!(report.entry_count(), 2);
+        assert_eq!(report.total_bytes(), 10);
+        assert_eq!(paq::Report::read_json(dir.read_file("source.paq").unwrap().as_slice()).unwrap(), report);
+
+        // algorithm is recorded by report
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg("verify")
+            .arg(source_dir.path().as_os_str())
+            .arg(format!("--against={}", hash_file.display()))
+            .assert()
+            .code(0)
+            .success();
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg(source_dir.path().as_os_str())
+            .arg("--algorithm=sha256")
+            .arg("--format=checksum")
+            .assert()
+            .code(0)
+            .success()
+            .stdout("b3e9fbaa4c955ed13978ee7443d306e3c7ca436491bbd10d714278872c266e37  alpha\n");
+
+        // single-file source is listed by file name
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg(source_dir.path().join("alpha").as_os_str())
+            .arg("--algorithm=sha256")
+            .arg("--format=checksum")
+            .assert()
+            .code(0)
+            .success()
+            .stdout("b3e9fbaa4c955ed13978ee7443d306e3c7ca436491bbd10d714278872c266e37  alpha\n");
+    }
+
+    #[test]
+    fn it_verifies_source_against_default_hash_file() {
+        let file_name = "alpha";
+        let file_contents = "alpha-body".as_bytes();
+        let dir = TempDir::new("it_verifies_source_against_default_hash_file").unwrap();
+        dir.new_file(file_name, file_contents).unwrap();
+        let source = dir.path().join(file_name);
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg(source.as_os_str())
+            .arg("-o")
+            .assert()
+            .success();
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        let assert = cmd
+            .arg("verify")
+            .arg(source.as_os_str())
+            .assert();
+        assert
+            .code(0)
+            .stdout(format!("{}: OK\n", source.display()))
+            .success();
+
+        dir.new_file(file_name, "alpha-changed".as_bytes()).unwrap();
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        let assert = cmd
+            .arg("verify")
+            .arg(source.as_os_str())
+            .assert();
+        assert
+            .code(1)
+            .stdout(format!("{}: FAILED\n", source.display()))
+            .failure();
+    }
+
+    #[test]
+    fn it_verifies_source_against_hash_file_long_arg() {
+        let file_name = "alpha";
+        let file_contents = "alpha-body".as_bytes();
+        let hash_file_name = "custom.paq";
+        let dir = TempDir::new("it_verifies_source_against_hash_file_long_arg").unwrap();
+        dir.new_file(file_name, file_contents).unwrap();
+        dir.new_file(
+            hash_file_name,
+            "\"48ec422c86fd2aa1ac182f832c10cf6cb07e4b89d88b83a7794bd8773460072c\"".as_bytes(),
+        ).unwrap();
+        let source = dir.path().join(file_name);
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        let assert = cmd
+            .arg("verify")
+            .arg(source.as_os_str())
+            .arg("--against")
+            .arg(dir.path().join(hash_file_name).as_os_str())
+            .assert();
+        assert
+            .code(0)
+            .success();
+    }
+
+    #[test]
+    fn it_verifies_source_against_hash_version_2_file() {
+        let file_name = "alpha";
+        let file_contents = "alpha-body".as_bytes();
+        let dir = TempDir::new("it_verifies_source_against_hash_version_2_file").unwrap();
+        dir.new_file(file_name, file_contents).unwrap();
+        let source = dir.path().join(file_name);
+        let hash_file = dir.path().join("alpha.paq");
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        let output = cmd
+            .arg(source.as_os_str())
+            .arg("--hash-version=2")
+            .arg(format!("--out={}", hash_file.display()))
+            .assert()
+            .code(0)
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        assert!(output.starts_with(b"paq2:"));
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        let assert = cmd
+            .arg("verify")
+            .arg(source.as_os_str())
+            .assert();
+        assert
+            .code(0)
+            .success();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn it_verifies_mode_recorded_by_manifest() {
+        use std::{
+            fs,
+            os::unix::fs::PermissionsExt,
+        };
+
+        let file_name = "script";
+        let dir = TempDir::new("it_verifies_mode_recorded_by_manifest").unwrap();
+        let source_dir = TempDir::new("it_verifies_mode_recorded_by_manifest/source").unwrap();
+        source_dir.new_file(file_name, "#!/bin/sh".as_bytes()).unwrap();
+        let file_path = source_dir.path().join(file_name);
+        fs::set_permissions(&file_path, fs::Permissions::from_mode(0o755)).unwrap();
+        let manifest = dir.path().join("manifest.json");
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd
+            .arg(source_dir.path().as_os_str())
+            .arg("--mode=exec")
+            .arg(format!("--manifest={}", manifest.display()))
+            .assert()
+            .code(0)
+            .success();
+
+        fs::set_permissions(&file_path, fs::Permissions::from_mode(0o644)).unwrap();
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        let assert = cmd
+            .arg("verify")
+            .arg(source_dir.path().as_os_str())
+            .arg("--against")
+            .arg(manifest.as_os_str())
+            .assert();
+        assert
+            .code(1)
+            .failure();
+    }
+
+    #[test]
+    fn it_outputs_hash_using_algorithm() {
+        let file_name = "alpha";
+        let file_contents = "alpha-body".as_bytes();
+        let dir = TempDir::new("it_outputs_hash_using_algorithm").unwrap();
+        dir.new_file(file_name, file_contents).unwrap();
+        let source = dir.path().join(file_name);
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        let assert = cmd
+            .arg(source.as_os_str())
+            .arg("--algorithm=sha256")
+            .assert();
+        assert
+            .code(0)
+            .stdout("sha256:15765433523517fa4b05e7ed259c3e779a75618644b52ee80112480433f87c34\n")
+            .success();
+    }
+
+    #[test]
+    fn it_verifies_source_using_key_file() {
+        let file_name = "alpha";
+        let file_contents = "alpha-body".as_bytes();
+        let dir = TempDir::new("it_verifies_source_using_key_file").unwrap();
+        let source_dir = TempDir::new("it_verifies_source_using_key_file/source").unwrap();
+        source_dir.new_file(file_name, file_contents).unwrap();
+        let key = "07".repeat(32);
+        dir.new_file("key", key.as_bytes()).unwrap();
+        let key_file = dir.path().join("key");
+        let hash_file = dir.path().join("source.paq");
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd
+            .arg(source_dir.path().as_os_str())
+            .arg("--key-file")
+            .arg(key_file.as_os_str())
+            .arg(format!("--out={}", hash_file.display()))
+            .assert()
+            .code(0)
+            .success();
+        let hash_file_contents = String::from_utf8(dir.read_file("source.paq").unwrap()).unwrap();
+        assert!(hash_file_contents.contains("\"root\": \"blake3-keyed:"));
+        assert!(!hash_file_contents.contains(&key));
+
+        // report records that options are keyed
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd
+            .arg("verify")
+            .arg(source_dir.path().as_os_str())
+            .assert()
+            .code(3)
+            .failure();
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd
+            .arg("verify")
+            .arg(source_dir.path().as_os_str())
+            .arg("--key-file")
+            .arg(key_file.as_os_str())
+            .assert()
+            .code(0)
+            .success();
+    }
+
+    #[test]
+    fn it_outputs_hash_with_extended_length() {
+        let file_name = "alpha";
+        let file_contents = "alpha-body".as_bytes();
+        let dir = TempDir::new("it_outputs_hash_with_extended_length").unwrap();
+        dir.new_file(file_name, file_contents).unwrap();
+        let source = dir.path().join(file_name);
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        let output = cmd
+            .arg(source.as_os_str())
+            .arg("--length=64")
+            .assert()
+            .code(0)
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.trim_end().len(), 128);
+        assert!(output.starts_with("48ec422c86fd2aa1ac182f832c10cf6cb07e4b89d88b83a7794bd8773460072c"));
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg(source.as_os_str()).arg("--length=1025").assert().code(2).failure();
+    }
+
+    #[test]
+    fn it_outputs_directory_digests_of_merkle_tree() {
+        let dir = TempDir::new("it_outputs_directory_digests_of_merkle_tree").unwrap();
+        let subdir = TempDir::new("it_outputs_directory_digests_of_merkle_tree/alpha").unwrap();
+        subdir.new_file("one", "one-body".as_bytes()).unwrap();
+        let source = dir.path().canonicalize().unwrap();
+        let tree = paq::HashOptions::new().tree(&source).unwrap();
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        let output = cmd
+            .arg(source.as_os_str())
+            .arg("--tree")
+            .assert()
+            .code(0)
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let expectation = format!(
+            "{}  .\n{}  alpha\n{}\n",
+            tree.merkle_root(),
+            tree.get(Path::new("alpha")).unwrap().to_hex(),
+            tree.root()
+        );
+        assert_eq!(String::from_utf8(output).unwrap(), expectation);
+    }
+
+    #[test]
+    fn it_outputs_hash_using_cache_file() {
+        let expectation = "48ec422c86fd2aa1ac182f832c10cf6cb07e4b89d88b83a7794bd8773460072c";
+        let file_name = "alpha";
+        let file_contents = "alpha-body".as_bytes();
+        let dir = TempDir::new("it_outputs_hash_using_cache_file").unwrap();
+        dir.new_file(file_name, file_contents).unwrap();
+        let source = dir.path().join(file_name);
+        let cache = dir.path().join("cache.json");
+
+        for _ in 0..2 {
+            let mut cmd = Command::new(cargo_bin!("paq"));
+            cmd.arg(source.as_os_str())
+                .arg("--cache")
+                .arg(cache.as_os_str())
+                .assert()
+                .code(0)
+                .success()
+                .stdout(format!("{expectation}\n"));
+        }
+        assert!(cache.is_file());
+    }
+
+    #[test]
+    fn it_rejects_cache_file_for_multiple_sources() {
+        let dir = TempDir::new("it_rejects_cache_file_for_multiple_sources").unwrap();
+        dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        dir.new_file("bravo", "bravo-body".as_bytes()).unwrap();
+        let cache = dir.path().join("cache.json");
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg(dir.path().join("alpha").as_os_str())
+            .arg(dir.path().join("bravo").as_os_str())
+            .arg("--cache")
+            .arg(cache.as_os_str())
+            .assert()
+            .code(3)
+            .failure()
+            .stderr("error: --cache requires a single source\n");
+        assert!(!cache.exists());
+    }
+
+    #[test]
+    fn it_outputs_hash_when_watched_source_changes() {
+        use std::{
+            io::{
+                BufRead,
+                BufReader,
+            },
+            iter,
+            process::Stdio,
+            sync::mpsc,
+            thread,
+            time::Duration,
+        };
+
+        let dir = TempDir::new("it_outputs_hash_when_watched_source_changes").unwrap();
+        dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        let source = dir.path().canonicalize().unwrap();
+
+        let mut child = std::process::Command::new(cargo_bin!("paq"))
+            .arg("watch")
+            .arg(source.as_os_str())
+            .arg("--debounce=100")
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in stdout.lines() {
+                if sender.send(line.unwrap()).is_err() {
+                    break;
+                }
+            }
+        });
+        let timeout = Duration::from_secs(10);
+
+        let initial = lines.recv_timeout(timeout).unwrap();
+        assert_eq!(initial, paq::HashOptions::new().hash(&source).unwrap());
+        dir.new_file("alpha", "changed-body".as_bytes()).unwrap();
+        // intermediate hash may be output if write is split across debounce windows
+        let expectation = paq::HashOptions::new().hash(&source).unwrap();
+        assert_ne!(expectation, initial);
+        assert!(iter::from_fn(|| lines.recv_timeout(timeout).ok()).any(|line| line == expectation));
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn it_outputs_hash_when_watched_working_directory_changes() {
+        use std::{
+            io::{
+                BufRead,
+                BufReader,
+            },
+            iter,
+            process::Stdio,
+            sync::mpsc,
+            thread,
+            time::Duration,
+        };
+
+        let dir = TempDir::new("it_outputs_hash_when_watched_working_directory_changes").unwrap();
+        dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        let source = dir.path().canonicalize().unwrap();
+
+        // source defaults to working directory
+        let mut child = std::process::Command::new(cargo_bin!("paq"))
+            .current_dir(&source)
+            .arg("watch")
+            .arg("--debounce=100")
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in stdout.lines() {
+                if sender.send(line.unwrap()).is_err() {
+                    break;
+                }
+            }
+        });
+        let timeout = Duration::from_secs(10);
+
+        let initial = lines.recv_timeout(timeout).unwrap();
+        assert_eq!(initial, paq::HashOptions::new().hash(&source).unwrap());
+        dir.new_file("alpha", "changed-body".as_bytes()).unwrap();
+        let expectation = paq::HashOptions::new().hash(&source).unwrap();
+        assert_ne!(expectation, initial);
+        assert!(iter::from_fn(|| lines.recv_timeout(timeout).ok()).any(|line| line == expectation));
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn it_hashes_archive_as_if_extracted() {
+        let dir = TempDir::new("it_hashes_archive_as_if_extracted").unwrap();
+        let subdir = TempDir::new("it_hashes_archive_as_if_extracted/source").unwrap();
+        subdir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        let mut builder = tar::Builder::new(Vec::new());
+        builder.append_dir_all(".", subdir.path()).unwrap();
+        let archive = dir.path().join("source.tar");
+        std::fs::write(&archive, builder.into_inner().unwrap()).unwrap();
+        let expectation = paq::HashOptions::new().hash(subdir.path()).unwrap();
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg(archive.as_os_str())
+            .arg("--archive")
+            .assert()
+            .code(0)
+            .success()
+            .stdout(format!("{expectation}\n"));
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg(archive.as_os_str())
+            .arg("--archive")
+            .arg("--ignore-files")
+            .assert()
+            .code(16)
+            .failure();
+    }
+
+    #[test]
+    fn it_hashes_tar_stream_from_stdin() {
+        let dir = TempDir::new("it_hashes_tar_stream_from_stdin").unwrap();
+        let subdir = TempDir::new("it_hashes_tar_stream_from_stdin/source").unwrap();
+        subdir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        let mut builder = tar::Builder::new(Vec::new());
+        builder.append_dir_all(".", subdir.path()).unwrap();
+        let tar = builder.into_inner().unwrap();
+        let archive = dir.path().join("source.tar");
+        std::fs::write(&archive, &tar).unwrap();
+        let expectation = paq::HashOptions::new().hash(subdir.path()).unwrap();
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg("-")
+            .write_stdin(tar.clone())
+            .assert()
+            .code(0)
+            .success()
+            .stdout(format!("{expectation}\n"));
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg(archive.as_os_str())
+            .arg("--tar")
+            .assert()
+            .code(0)
+            .success()
+            .stdout(format!("{expectation}\n"));
+
+        // default output filepath is derived from source path
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg("-")
+            .arg("--out")
+            .write_stdin(tar)
+            .assert()
+            .code(3)
+            .failure();
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg("-")
+            .write_stdin("not a tar stream")
+            .assert()
+            .code(16)
+            .failure();
+    }
+
+    #[test]
+    fn it_hashes_multiple_sources() {
+        let dir = TempDir::new("it_hashes_multiple_sources").unwrap();
+        let alpha_dir = TempDir::new("it_hashes_multiple_sources/alpha").unwrap();
+        let bravo_dir = TempDir::new("it_hashes_multiple_sources/bravo").unwrap();
+        alpha_dir.new_file("charlie", "charlie-body".as_bytes()).unwrap();
+        bravo_dir.new_file("delta", "delta-body".as_bytes()).unwrap();
+        let options = paq::HashOptions::new();
+        let alpha = options.hash(alpha_dir.path()).unwrap();
+        let bravo = options.hash(bravo_dir.path()).unwrap();
+        let combined = options.combine([("alpha", &alpha), ("bravo", &bravo)]);
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.current_dir(dir.path())
+            .arg("alpha")
+            .arg("bravo")
+            .arg("--combine")
+            .arg("--out")
+            .assert()
+            .code(0)
+            .success()
+            .stdout(format!("{alpha}  alpha\n{bravo}  bravo\n{combined}\n"));
+        for (name, hash) in [("alpha.paq", &alpha), ("bravo.paq", &bravo)] {
+            let report = paq::Report::read_json(dir.read_file(name).unwrap().as_slice()).unwrap();
+            assert_eq!(report.root(), hash.as_str());
+        }
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.current_dir(dir.path())
+            .arg("alpha")
+            .arg("bravo")
+            .arg("--tree")
+            .assert()
+            .code(3)
+            .failure();
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.current_dir(dir.path())
+            .arg("alpha")
+            .arg("alpha/charlie")
+            .arg("alpha/echo")
+            .assert()
+            .code(2)
+            .failure();
+    }
+
+    #[test]
+    fn it_outputs_progress_to_stderr() {
+        let dir = TempDir::new("it_outputs_progress_to_stderr").unwrap();
+        dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        let expectation = paq::HashOptions::new().hash(dir.path()).unwrap();
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        let output = cmd
+            .arg(dir.path().as_os_str())
+            .arg("--progress")
+            .assert()
+            .code(0)
+            .success()
+            .stdout(format!("{expectation}\n"))
+            .get_output()
+            .stderr
+            .clone();
+        // final line only, as stderr is not a terminal (totals shown once pre-scan completes)
+        let stderr = String::from_utf8(output).unwrap();
+        assert!(
+            stderr.starts_with("1/1 files, 10 B/10 B, ") || stderr.starts_with("1 files, 10 B, "),
+            "{stderr}"
+        );
+        assert_eq!(stderr.lines().count(), 1);
+    }
+
+    #[test]
+    fn it_hashes_using_thread_count() {
+        let dir = TempDir::new("it_hashes_using_thread_count").unwrap();
+        dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        dir.new_file("bravo", "bravo-body".as_bytes()).unwrap();
+        let expectation = paq::HashOptions::new().hash(dir.path()).unwrap();
+
+        for threads in ["0", "1", "3"] {
+            let mut cmd = Command::new(cargo_bin!("paq"));
+            cmd.arg(dir.path().as_os_str())
+                .arg(format!("--threads={threads}"))
+                .assert()
+                .code(0)
+                .success()
+                .stdout(format!("{expectation}\n"));
+        }
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg(dir.path().as_os_str())
+            .env("PAQ_THREADS", "1")
+            .assert()
+            .code(0)
+            .success()
+            .stdout(format!("{expectation}\n"));
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg(dir.path().as_os_str())
+            .env("PAQ_THREADS", "many")
+            .assert()
+            .code(2)
+            .failure();
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn it_reports_entries_skipped_or_recorded_on_error() {
+        let dir = TempDir::new("it_reports_entries_skipped_or_recorded_on_error").unwrap();
+        dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        dir.new_symlink("symlink", PathBuf::from("missing")).unwrap();
+        let source = dir.path().canonicalize().unwrap();
+        let options = paq::HashOptions::new().follow_symlinks(true);
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg(source.as_os_str())
+            .arg("-L")
+            .assert()
+            .code(13)
+            .failure();
+
+        // error report is written to stderr without file
+        let expectation = options.clone().on_error(paq::OnError::Skip).hash(&source).unwrap();
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        let output = cmd.arg(source.as_os_str())
+            .arg("-L")
+            .arg("--on-error=skip")
+            .assert()
+            .code(17)
+            .failure()
+            .stdout(format!("{expectation}\n"))
+            .get_output()
+            .clone();
+        let report: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+        assert_eq!(report["errors"][0]["path"], "symlink");
+        assert_eq!(report["errors"][0]["error"], "dangling_symlink");
+        assert_eq!(report["errors"][0]["source"], source.to_str().unwrap());
+
+        let report_file = dir.path().join("errors.json");
+        let expectation = options.on_error(paq::OnError::Record).hash(&source).unwrap();
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg(source.as_os_str())
+            .arg("-L")
+            .arg("--on-error=record")
+            .arg("--error-report")
+            .arg(report_file.as_os_str())
+            .assert()
+            .code(17)
+            .failure()
+            .stdout(format!("{expectation}\n"))
+            .stderr("");
+        let report: serde_json::Value = serde_json::from_slice(&std::fs::read(&report_file).unwrap()).unwrap();
+        assert_eq!(report["errors"].as_array().unwrap().len(), 1);
+
+        // report file is written even without errors
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg(dir.path().join("alpha").as_os_str())
+            .arg("--on-error=record")
+            .arg("--error-report")
+            .arg(report_file.as_os_str())
+            .assert()
+            .code(0)
+            .success();
+        let report: serde_json::Value = serde_json::from_slice(&std::fs::read(&report_file).unwrap()).unwrap();
+        assert!(report["errors"].as_array().unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_diffs_manifest_against_directory() {
+        let file_name = "alpha";
+        let manifest_file_name = "manifest.json";
+        let dir = TempDir::new("it_diffs_manifest_against_directory").unwrap();
+        let source_dir = TempDir::new("it_diffs_manifest_against_directory/source").unwrap();
+        source_dir.new_file(file_name, "alpha-body".as_bytes()).unwrap();
+        let manifest = dir.path().join(manifest_file_name);
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg(source_dir.path().as_os_str())
+            .arg(format!("--manifest={}", manifest.display()))
+            .assert()
+            .success();
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg("diff")
+            .arg(manifest.as_os_str())
+            .arg(source_dir.path().as_os_str())
+            .assert()
+            .code(0)
+            .stdout("")
+            .success();
+
+        source_dir.new_file(file_name, "alpha-changed".as_bytes()).unwrap();
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg("diff")
+            .arg(manifest.as_os_str())
+            .arg(source_dir.path().as_os_str())
+            .assert()
+            .code(1)
+            .stdout("M alpha\n")
+            .failure();
+
+        // source is hashed using options recorded by manifest
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg(source_dir.path().as_os_str())
+            .arg("--hash-version=2")
+            .arg(format!("--manifest={}", manifest.display()))
+            .assert()
+            .success();
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg("diff")
+            .arg(manifest.as_os_str())
+            .arg(source_dir.path().as_os_str())
+            .assert()
+            .code(0)
+            .stdout("")
+            .success();
+
+        // source root is displayed as .
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg("diff")
+            .arg(source_dir.path().as_os_str())
+            .arg(source_dir.path().join(file_name).as_os_str())
+            .assert()
+            .code(1)
+            .stdout("T . (dir -> file)\nD alpha\n")
+            .failure();
+
+        // manifests of different algorithms cannot be compared
+        let other_manifest = dir.path().join("other.json");
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg(source_dir.path().as_os_str())
+            .arg("--algorithm=sha256")
+            .arg(format!("--manifest={}", other_manifest.display()))
+            .assert()
+            .success();
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg("diff")
+            .arg(manifest.as_os_str())
+            .arg(other_manifest.as_os_str())
+            .assert()
+            .code(3)
+            .failure();
+    }
+
+    #[test]
+    fn it_fails_to_diff_hash_file_or_corrupt_manifest() {
+        let dir = TempDir::new("it_fails_to_diff_hash_file_or_corrupt_manifest").unwrap();
+        let source_dir = TempDir::new("it_fails_to_diff_hash_file_or_corrupt_manifest/source").unwrap();
+        source_dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        let report = dir.path().join("source.paq");
+        let manifest = dir.path().join("manifest.json");
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg(source_dir.path().as_os_str())
+            .arg(format!("--out={}", report.display()))
+            .arg(format!("--manifest={}", manifest.display()))
+            .assert()
+            .success();
+
+        // reports have no entries to compare
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg("diff")
+            .arg(report.as_os_str())
+            .arg(source_dir.path().as_os_str())
+            .assert()
+            .code(18)
+            .stdout("")
+            .failure();
+
+        // truncated and corrupt manifests are not hashed as files
+        let contents = std::fs::read(&manifest).unwrap();
+        std::fs::write(&manifest, &contents[..contents.len() / 2]).unwrap();
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg("diff")
+            .arg(manifest.as_os_str())
+            .arg(source_dir.path().as_os_str())
+            .assert()
+            .code(18)
+            .stdout("")
+            .failure();
+        std::fs::write(&manifest, r#"{"root":"00","options":{},"entries":[{}]}"#).unwrap();
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg("diff")
+            .arg(source_dir.path().as_os_str())
+            .arg(manifest.as_os_str())
+            .assert()
+            .code(18)
+            .stdout("")
+            .failure();
+    }
+
+    #[test]
+    fn it_diffs_ordinary_json_files_as_sources() {
+        let dir = TempDir::new("it_diffs_ordinary_json_files_as_sources").unwrap();
+        dir.new_file("package.json", r#"{"name":"alpha","version":"1.0.0"}"#.as_bytes()).unwrap();
+        dir.new_file("other.json", r#"{"x":1}"#.as_bytes()).unwrap();
+        dir.new_file("same.json", r#"{"x":1}"#.as_bytes()).unwrap();
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg("diff")
+            .arg(dir.path().join("other.json").as_os_str())
+            .arg(dir.path().join("same.json").as_os_str())
+            .assert()
+            .code(0)
+            .success();
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg("diff")
+            .arg(dir.path().join("package.json").as_os_str())
+            .arg(dir.path().join("other.json").as_os_str())
+            .assert()
+            .code(1)
+            .failure();
+    }
+
+    #[test]
+    fn it_verifies_source_using_options_recorded_by_manifest() {
+        let manifest_file_name = "manifest.json";
+        let dir = TempDir::new("it_verifies_source_using_options_recorded_by_manifest").unwrap();
+        let source_dir = TempDir::new("it_verifies_source_using_options_recorded_by_manifest/source").unwrap();
+        source_dir.new_file(".paqignore", "*.log\n".as_bytes()).unwrap();
+        source_dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        let manifest = dir.path().join(manifest_file_name);
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg(source_dir.path().as_os_str())
+            .arg("--ignore-files")
+            .arg(format!("--manifest={}", manifest.display()))
+            .assert()
+            .success();
+
+        source_dir.new_file("alpha.log", "alpha-log".as_bytes()).unwrap();
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg("verify")
+            .arg(source_dir.path().as_os_str())
+            .arg(format!("--against={}", manifest.display()))
+            .assert()
+            .code(0)
+            .stderr("")
+            .success();
+
+        // hashing arguments are reported as ignored
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg("verify")
+            .arg(source_dir.path().as_os_str())
+            .arg(format!("--against={}", manifest.display()))
+            .arg("--algorithm=sha256")
+            .arg("--ignore-hidden")
+            .assert()
+            .code(0)
+            .stderr(format!(
+                "warning: --ignore-hidden, --algorithm ignored, verifying using options recorded by {}\n",
+                manifest.display()
+            ))
+            .success();
+    }
+
+    #[test]
+    fn it_excludes_patterns_read_from_file() {
+        let expectation = "7ed5febd35e277763cdfc3e4bee136acf38e48e9462972a732cc4d348a37d653";
+
+        let dir = TempDir::new("it_excludes_patterns_read_from_file").unwrap();
+        let source_dir = TempDir::new("it_excludes_patterns_read_from_file/source").unwrap();
+        source_dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        source_dir.new_file("bravo.log", "bravo-body".as_bytes()).unwrap();
+        dir.new_file("patterns", "# logs\n\n*.log\n".as_bytes()).unwrap();
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg(source_dir.path().canonicalize().unwrap().as_os_str())
+            .arg("--exclude-from")
+            .arg(dir.path().join("patterns").as_os_str())
+            .assert()
+            .code(0)
+            .stdout(format!("{expectation}\n"))
+            .success();
+    }
+
+    #[test]
+    fn it_applies_global_git_excludes_only_if_requested() {
+        let dir = TempDir::new("it_applies_global_git_excludes_only_if_requested").unwrap();
+        let config_dir = TempDir::new("it_applies_global_git_excludes_only_if_requested/config/git").unwrap();
+        config_dir.new_file("ignore", "*.log\n".as_bytes()).unwrap();
+        let source_dir = TempDir::new("it_applies_global_git_excludes_only_if_requested/source").unwrap();
+        source_dir.new_file("alpha", "alpha-body".as_bytes()).unwrap();
+        source_dir.new_file("bravo.log", "bravo-body".as_bytes()).unwrap();
+        let source = source_dir.path().canonicalize().unwrap();
+        let options = paq::HashOptions::new().ignore_files(true);
+        let expectation = options.hash(&source).unwrap();
+        let expectation_excluded = options.exclude(["*.log"]).hash(&source).unwrap();
+        assert_ne!(expectation, expectation_excluded);
+
+        // global excludes of hashing user do not apply unless requested
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        cmd.arg(source.as_os_str())
+            .arg("--ignore-files")
+            .env("HOME", dir.path())
+            .env("XDG_CONFIG_HOME", dir.path().join("config"))
+            .assert()
+            .code(0)
+            .stdout(format!("{expectation}\n"))
+            .success();
+
+        let mut cmd = Command::new(cargo_bin!("paq"));
+        let output = cmd.arg(source.as_os_str())
+            .arg("--ignore-files")
+            .arg("--global-ignore")
+            .arg("--format=json")
+            .env("HOME", dir.path())
+            .env("XDG_CONFIG_HOME", dir.path().join("config"))
+            .assert()
+            .code(0)
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let report: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(report["root"], expectation_excluded);
+        assert_eq!(report["options"]["global_ignore"], true);
     }
 }